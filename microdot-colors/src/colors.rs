@@ -1,4 +1,4 @@
-use crate::palettes::PaletteReader;
+use crate::palettes::{Palette, PaletteReader};
 use palette::*;
 use std::fmt::{Display, Formatter};
 
@@ -193,6 +193,8 @@ pub struct ColorScheme {
 // const PALETTE_NAME: &str = "generated";
 // const PALETTE_NAME: &str = "large";
 const PALETTE_NAME: &str = "stretched";
+const HEAT_PALETTE_NAME: &str = "heat";
+const HEAT_STEPS: usize = 20;
 
 impl ColorScheme {
     const NODE_BORDER_WIDTH: f64 = 3.0f64;
@@ -226,6 +228,15 @@ impl ColorScheme {
         ColorScheme::from_entry(highlight)
     }
 
+    /// a gradient running from cool to hot, for colouring nodes by a value.
+    pub fn heat() -> Palette {
+        let content = include_str!("./palettes.txt");
+        let reader = PaletteReader {};
+        let palettes = reader.read(content).expect("couldn't read palette");
+        let palette = palettes.get(HEAT_PALETTE_NAME).unwrap();
+        palette.stretch(HEAT_STEPS)
+    }
+
     pub fn get_stroke_color(&self) -> Color {
         self.stroke_color
     }
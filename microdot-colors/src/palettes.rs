@@ -20,6 +20,29 @@ impl Palette {
         let index = index % self.colors.len();
         self.colors[index]
     }
+
+    /// spreads the colours of this palette out into a smooth gradient of `n` colours.
+    pub fn stretch(&self, n: usize) -> Palette {
+        Palette {
+            colors: generate_gradient_palette(&self.colors, n),
+        }
+    }
+
+    /// picks a colour by its position along the palette, from 0.0 (first) to 1.0 (last).
+    pub fn get_gradient_color(&self, position: f64) -> Color {
+        if self.colors.is_empty() {
+            return Colors::white();
+        }
+
+        let last = self.colors.len() - 1;
+        let position = if position.is_nan() {
+            0.0
+        } else {
+            position.clamp(0.0, 1.0)
+        };
+        let index = (position * last as f64).round() as usize;
+        self.colors[index]
+    }
 }
 
 pub struct PaletteReader {}
@@ -216,6 +239,22 @@ mod tests {
         assert_eq!(Color::from_rgb(0, 255, 255), palette.get_fill_color(3));
     }
 
+    #[test]
+    fn can_pick_gradient_colors() {
+        let content = "bw: #000000 #ffffff";
+        let reader = PaletteReader {};
+        let palettes = reader.read(content).unwrap();
+        let gradient = palettes.get("bw").unwrap().stretch(11);
+        assert_eq!(Colors::black(), gradient.get_gradient_color(0.0));
+        assert_eq!(Colors::white(), gradient.get_gradient_color(1.0));
+        assert_eq!(
+            Color::from_rgb(127, 127, 127),
+            gradient.get_gradient_color(0.5)
+        );
+        assert_eq!(Colors::black(), gradient.get_gradient_color(-1.0));
+        assert_eq!(Colors::white(), gradient.get_gradient_color(2.0));
+    }
+
     #[test]
     fn can_read_palette_file() {
         let content = include_str!("./palettes.txt");
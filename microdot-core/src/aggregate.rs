@@ -0,0 +1,231 @@
+//! Aggregation of variable values, eg for `cost <var> <agg>`.
+use crate::graph::{Time, VariableType, VariableValue};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use strum_macros::{Display, EnumString};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Aggregation {
+    Sum,
+    Min,
+    Max,
+    Avg,
+    Count,
+    Median,
+    All,
+    Any,
+}
+
+impl Aggregation {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Aggregation::Sum => "sum",
+            Aggregation::Min => "minimum",
+            Aggregation::Max => "maximum",
+            Aggregation::Avg => "average",
+            Aggregation::Count => "count",
+            Aggregation::Median => "median",
+            Aggregation::All => "logical and",
+            Aggregation::Any => "logical or",
+        }
+    }
+}
+
+/// The result of an aggregation, broken down by the type of the values. Most of the time there
+/// is only one type, but labels like `$t=1d` and `$t=2` mix times with numbers, and those can't
+/// be meaningfully combined.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregated {
+    pub by_type: BTreeMap<VariableType, Result<VariableValue, String>>,
+}
+
+impl Display for Aggregated {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        fn show(result: &Result<VariableValue, String>) -> String {
+            match result {
+                Ok(value) => value.to_string(),
+                Err(e) => format!("({})", e),
+            }
+        }
+
+        match self.by_type.len() {
+            0 => write!(f, "no values"),
+            1 => write!(f, "{}", show(self.by_type.values().next().unwrap())),
+            _ => {
+                let parts: Vec<_> = self
+                    .by_type
+                    .iter()
+                    .map(|(typ, result)| format!("{}: {}", typ, show(result)))
+                    .collect();
+                write!(f, "{}", parts.join("; "))
+            }
+        }
+    }
+}
+
+/// Aggregates values, grouping them by type first so mixed values get a per-type breakdown.
+pub fn aggregate(
+    values: impl IntoIterator<Item = VariableValue>,
+    aggregation: Aggregation,
+) -> Aggregated {
+    let mut grouped: BTreeMap<VariableType, Vec<VariableValue>> = BTreeMap::new();
+    for value in values {
+        push_flattened(&mut grouped, value);
+    }
+
+    let by_type = grouped
+        .into_iter()
+        .map(|(typ, values)| {
+            let result = aggregate_same_type(&typ, values, aggregation);
+            (typ, result)
+        })
+        .collect();
+
+    Aggregated { by_type }
+}
+
+fn push_flattened(grouped: &mut BTreeMap<VariableType, Vec<VariableValue>>, value: VariableValue) {
+    match value {
+        VariableValue::Mixed(values) => {
+            for value in values {
+                push_flattened(grouped, value);
+            }
+        }
+        value => grouped.entry(value.typ()).or_default().push(value),
+    }
+}
+
+fn aggregate_same_type(
+    typ: &VariableType,
+    mut values: Vec<VariableValue>,
+    aggregation: Aggregation,
+) -> Result<VariableValue, String> {
+    use Aggregation::*;
+
+    let count = values.len();
+    let not_defined = || Err(format!("{} is not defined for {}", aggregation, typ));
+
+    match (aggregation, typ) {
+        (Count, _) => Ok(VariableValue::number(count as f64)),
+        (Sum, VariableType::Number | VariableType::Time) => Ok(values.into_iter().sum()),
+        (Min, VariableType::Number | VariableType::Time | VariableType::String) => {
+            Ok(values.into_iter().min().unwrap_or_else(VariableValue::zero))
+        }
+        (Max, VariableType::Number | VariableType::Time | VariableType::String) => {
+            Ok(values.into_iter().max().unwrap_or_else(VariableValue::zero))
+        }
+        (Avg, VariableType::Number) => {
+            let total: f64 = values.iter().filter_map(VariableValue::as_f64).sum();
+            Ok(VariableValue::number(total / count as f64))
+        }
+        (Avg, VariableType::Time) => {
+            let total: f64 = values.iter().filter_map(VariableValue::as_f64).sum();
+            let minutes = (total / count as f64).round() as i32;
+            Ok(VariableValue::time(Time::Minute(minutes)))
+        }
+        (Median, VariableType::Number | VariableType::Time) => {
+            values.sort();
+            let mid = count / 2;
+            if count % 2 == 1 {
+                Ok(values[mid].clone())
+            } else {
+                let lower = values[mid - 1].as_f64().unwrap_or_default();
+                let upper = values[mid].as_f64().unwrap_or_default();
+                let median = (lower + upper) / 2.0;
+                Ok(match typ {
+                    VariableType::Time => VariableValue::time(Time::Minute(median.round() as i32)),
+                    _ => VariableValue::number(median),
+                })
+            }
+        }
+        (All, VariableType::Boolean) => Ok(VariableValue::boolean(
+            values.iter().all(|v| v == &VariableValue::boolean(true)),
+        )),
+        (Any, VariableType::Boolean) => Ok(VariableValue::boolean(
+            values.iter().any(|v| v == &VariableValue::boolean(true)),
+        )),
+        _ => not_defined(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbers(ns: &[f64]) -> Vec<VariableValue> {
+        ns.iter().map(|n| VariableValue::number(*n)).collect()
+    }
+
+    fn single(aggregated: Aggregated) -> Result<VariableValue, String> {
+        assert_eq!(aggregated.by_type.len(), 1);
+        aggregated.by_type.into_values().next().unwrap()
+    }
+
+    #[test]
+    fn it_parses_aggregation_names() {
+        assert_eq!(
+            "median".parse::<Aggregation>().unwrap(),
+            Aggregation::Median
+        );
+        assert_eq!("avg".parse::<Aggregation>().unwrap(), Aggregation::Avg);
+        assert!("average".parse::<Aggregation>().is_err());
+    }
+
+    #[test]
+    fn it_aggregates_numbers() {
+        let values = numbers(&[3.0, 1.0, 4.0, 2.0]);
+        let agg = |a| single(aggregate(values.clone(), a)).unwrap();
+        assert_eq!(agg(Aggregation::Sum), VariableValue::number(10.0));
+        assert_eq!(agg(Aggregation::Min), VariableValue::number(1.0));
+        assert_eq!(agg(Aggregation::Max), VariableValue::number(4.0));
+        assert_eq!(agg(Aggregation::Avg), VariableValue::number(2.5));
+        assert_eq!(agg(Aggregation::Count), VariableValue::number(4.0));
+        assert_eq!(agg(Aggregation::Median), VariableValue::number(2.5));
+        assert!(single(aggregate(values, Aggregation::All)).is_err());
+    }
+
+    #[test]
+    fn it_aggregates_times() {
+        let values = vec![
+            VariableValue::time(Time::Hour(1)),
+            VariableValue::time(Time::Minute(30)),
+            VariableValue::time(Time::Day(1)),
+        ];
+        let agg = |a| single(aggregate(values.clone(), a)).unwrap();
+        assert_eq!(agg(Aggregation::Median), VariableValue::time(Time::Hour(1)));
+        assert_eq!(agg(Aggregation::Max), VariableValue::time(Time::Day(1)));
+        assert_eq!(
+            agg(Aggregation::Avg),
+            VariableValue::time(Time::Minute(190))
+        );
+    }
+
+    #[test]
+    fn it_aggregates_booleans() {
+        let values = vec![VariableValue::boolean(true), VariableValue::boolean(false)];
+        let agg = |a| single(aggregate(values.clone(), a));
+        assert_eq!(agg(Aggregation::All), Ok(VariableValue::boolean(false)));
+        assert_eq!(agg(Aggregation::Any), Ok(VariableValue::boolean(true)));
+        assert_eq!(agg(Aggregation::Count), Ok(VariableValue::number(2.0)));
+        assert_eq!(
+            agg(Aggregation::Sum),
+            Err("sum is not defined for booleans".to_string())
+        );
+    }
+
+    #[test]
+    fn it_breaks_mixed_values_down_by_type() {
+        let values = vec![
+            VariableValue::number(2.0),
+            VariableValue::time(Time::Hour(1)),
+            VariableValue::number(4.0),
+            VariableValue::string("x"),
+        ];
+        let aggregated = aggregate(values, Aggregation::Avg);
+        assert_eq!(
+            aggregated.to_string(),
+            "text: (avg is not defined for text); numbers: 3; times: 1 hour"
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use crate::{Id, Label};
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
 pub enum GraphCommand {
     DeleteNode { id: Id, keep_edges: bool },
     ExpandEdge { id: Id, label: Label },
@@ -0,0 +1,594 @@
+//! Computed variables. A variable whose value references other variables is a formula, eg
+//! `$effort=$t*$people` or `$total=sum(children.$cost)`, and is evaluated on demand.
+use crate::aggregate::{aggregate, Aggregation};
+use crate::graph::{Time, Variable, VariableValue};
+use crate::labels::Variables;
+use crate::Id;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+
+/// Which neighbours an aggregate like `sum(children.$cost)` looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Children,
+    Parents,
+}
+
+impl Display for Relation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Relation::Children => write!(f, "children"),
+            Relation::Parents => write!(f, "parents"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Op::Add => write!(f, "add"),
+            Op::Subtract => write!(f, "subtract"),
+            Op::Multiply => write!(f, "multiply"),
+            Op::Divide => write!(f, "divide"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Value(VariableValue),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Aggregate {
+        aggregation: Aggregation,
+        relation: Relation,
+        variable_name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormulaError {
+    UnknownVariable(String),
+    /// the variables in the loop, eg `["$a", "$b", "$a"]`.
+    Cycle(Vec<String>),
+    /// the formula aggregates over neighbours, but there's no graph to look them up in.
+    NeedsGraph,
+    Invalid(String),
+}
+
+impl Display for FormulaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormulaError::UnknownVariable(name) => write!(f, "unknown variable ${}", name),
+            FormulaError::Cycle(names) => write!(f, "cycle {}", names.join(" -> ")),
+            FormulaError::NeedsGraph => write!(f, "needs the whole graph"),
+            FormulaError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    expr: Expr,
+}
+
+impl Formula {
+    /// Parses a variable's value as a formula. Only text which references another variable
+    /// counts, so values like `x+1` stay plain strings.
+    pub fn parse(text: &str) -> Option<Self> {
+        if !text.contains('$') {
+            return None;
+        }
+
+        let mut parser = FormulaParser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.pos != parser.chars.len() {
+            return None;
+        }
+
+        Some(Formula { expr })
+    }
+
+    pub fn from_value(value: &VariableValue) -> Option<Self> {
+        match value {
+            VariableValue::String(text) => Self::parse(text),
+            _ => None,
+        }
+    }
+}
+
+struct FormulaParser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl FormulaParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, expected: char) -> Option<()> {
+        if self.peek() == Some(expected) {
+            self.pos += 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    // expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Option<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => Op::Add,
+                Some('-') => Op::Subtract,
+                _ => return Some(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    // term := unary (('*' | '/') unary)*
+    fn term(&mut self) -> Option<Expr> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => Op::Multiply,
+                Some('/') => Op::Divide,
+                _ => return Some(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.unary()?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Option<Expr> {
+        if self.eat('-').is_some() {
+            return Some(Expr::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    // primary := '(' expr ')' | '$' name | number [unit] | aggregation '(' relation '.$' name ')'
+    fn primary(&mut self) -> Option<Expr> {
+        match self.peek()? {
+            '(' => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.eat(')')?;
+                Some(expr)
+            }
+            '$' => {
+                self.pos += 1;
+                Some(Expr::Variable(self.name()?))
+            }
+            c if c.is_ascii_digit() || c == '.' => self.number(),
+            c if c.is_ascii_alphabetic() => self.aggregate(),
+            _ => None,
+        }
+    }
+
+    /// variable names as in labels; a hyphen only continues the name when a letter follows, so
+    /// `$a-$b` subtracts.
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        if !self.peek()?.is_ascii_alphabetic() {
+            return None;
+        }
+        while let Some(c) = self.peek() {
+            let continues_with_letter = self
+                .chars
+                .get(self.pos + 1)
+                .is_some_and(|next| next.is_ascii_alphabetic());
+            if c.is_ascii_alphanumeric() || c == '_' || (c == '-' && continues_with_letter) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn number(&mut self) -> Option<Expr> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+
+        let unit = self.peek().filter(|c| "mhdMy".contains(*c));
+        let unit_ends =
+            !matches!(self.chars.get(self.pos + 1), Some(c) if c.is_ascii_alphanumeric());
+        if let (Some(unit), true) = (unit, unit_ends) {
+            self.pos += 1;
+            let time = Time::parse(&format!("{}{}", digits.parse::<i32>().ok()?, unit))?;
+            return Some(Expr::Value(VariableValue::time(time)));
+        }
+
+        Some(Expr::Value(VariableValue::number(digits.parse().ok()?)))
+    }
+
+    fn aggregate(&mut self) -> Option<Expr> {
+        let aggregation = self.word()?.parse::<Aggregation>().ok()?;
+        self.eat('(')?;
+        let relation = match self.word()?.as_str() {
+            "children" | "successors" => Relation::Children,
+            "parents" | "predecessors" => Relation::Parents,
+            _ => return None,
+        };
+        self.eat('.')?;
+        self.eat('$')?;
+        let variable_name = self.name()?;
+        self.eat(')')?;
+
+        Some(Expr::Aggregate {
+            aggregation,
+            relation,
+            variable_name,
+        })
+    }
+
+    fn word(&mut self) -> Option<String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        (self.pos > start).then(|| self.chars[start..self.pos].iter().collect())
+    }
+}
+
+/// Evaluates variables, following formulas through the node's other variables and its
+/// neighbours. Results are remembered, so each variable is only worked out once.
+pub struct Evaluator {
+    variables: BTreeMap<Id, Variables>,
+    children: BTreeMap<Id, Vec<Id>>,
+    parents: BTreeMap<Id, Vec<Id>>,
+    standalone: bool,
+    memo: HashMap<(Id, String), Result<Option<VariableValue>, FormulaError>>,
+    stack: Vec<(Id, String)>,
+}
+
+impl Evaluator {
+    /// an evaluator over a whole graph, given each node's variables and the edges between them.
+    pub fn new(variables: BTreeMap<Id, Variables>, edges: Vec<(Id, Id)>) -> Self {
+        let mut children: BTreeMap<Id, Vec<Id>> = BTreeMap::new();
+        let mut parents: BTreeMap<Id, Vec<Id>> = BTreeMap::new();
+        for (from, to) in edges {
+            children.entry(from.clone()).or_default().push(to.clone());
+            parents.entry(to).or_default().push(from);
+        }
+
+        Evaluator {
+            variables,
+            children,
+            parents,
+            standalone: false,
+            memo: HashMap::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    /// an evaluator for a single node's variables, with no neighbours to aggregate over.
+    pub(crate) fn standalone(variables: Variables) -> Self {
+        let mut evaluator = Self::new(BTreeMap::from([(Self::standalone_id(), variables)]), vec![]);
+        evaluator.standalone = true;
+        evaluator
+    }
+
+    pub(crate) fn standalone_id() -> Id {
+        Id::new("")
+    }
+
+    pub fn ids(&self) -> Vec<Id> {
+        self.variables.keys().cloned().collect()
+    }
+
+    /// the value of a node's variable, or `None` if the node doesn't have it.
+    pub fn value(&mut self, id: &Id, name: &str) -> Result<Option<VariableValue>, FormulaError> {
+        let key = (id.clone(), name.to_string());
+        if let Some(result) = self.memo.get(&key) {
+            return result.clone();
+        }
+
+        if let Some(start) = self.stack.iter().position(|k| k == &key) {
+            let mut keys = self.stack[start..].to_vec();
+            keys.push(key);
+            // only mention the nodes when the loop runs between them.
+            let same_node = keys.iter().all(|(other, _)| other == id);
+            let names = keys
+                .into_iter()
+                .map(|(id, name)| match same_node {
+                    true => format!("${}", name),
+                    false => format!("{}.${}", id, name),
+                })
+                .collect();
+            return Err(FormulaError::Cycle(names));
+        }
+
+        let raw = self
+            .variables
+            .get(id)
+            .and_then(|variables| variables.get(name))
+            .map(|variable| variable.value.clone());
+
+        let result = match raw {
+            None => Ok(None),
+            Some(raw) => match Formula::from_value(&raw) {
+                None => Ok(Some(raw)),
+                Some(formula) => {
+                    self.stack.push(key.clone());
+                    let result = self.evaluate(&formula.expr, id).map(Some);
+                    self.stack.pop();
+                    result
+                }
+            },
+        };
+
+        self.memo.insert(key, result.clone());
+        result
+    }
+
+    /// all of a node's variables with formulas worked out. Formulas which fail show the error
+    /// in place of the value, so it's visible on the diagram.
+    pub fn variables(&mut self, id: &Id) -> Variables {
+        let raw: Vec<Variable> = self
+            .variables
+            .get(id)
+            .map(|variables| variables.iter().cloned().collect())
+            .unwrap_or_default();
+
+        let evaluated = raw
+            .into_iter()
+            .map(|variable| {
+                let value = match self.value(id, &variable.name) {
+                    Ok(Some(value)) => value,
+                    Ok(None) | Err(FormulaError::NeedsGraph) => variable.value.clone(),
+                    Err(e) => VariableValue::string(format!("error: {}", e)),
+                };
+                Variable::new(variable.name, value)
+            })
+            .collect();
+
+        Variables::from_vec(evaluated)
+    }
+
+    fn evaluate(&mut self, expr: &Expr, id: &Id) -> Result<VariableValue, FormulaError> {
+        match expr {
+            Expr::Value(value) => Ok(value.clone()),
+            Expr::Variable(name) => self
+                .value(id, name)?
+                .ok_or_else(|| FormulaError::UnknownVariable(name.clone())),
+            Expr::Negate(inner) => match self.evaluate(inner, id)? {
+                value @ (VariableValue::Number(_) | VariableValue::Time(_)) => Ok(-value),
+                value => Err(FormulaError::Invalid(format!(
+                    "cannot negate {}",
+                    value.typ()
+                ))),
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.evaluate(lhs, id)?;
+                let rhs = self.evaluate(rhs, id)?;
+                apply(*op, lhs, rhs)
+            }
+            Expr::Aggregate {
+                aggregation,
+                relation,
+                variable_name,
+            } => {
+                if self.standalone {
+                    return Err(FormulaError::NeedsGraph);
+                }
+
+                let neighbours = match relation {
+                    Relation::Children => self.children.get(id),
+                    Relation::Parents => self.parents.get(id),
+                };
+                let neighbours = neighbours.cloned().unwrap_or_default();
+
+                let mut values = vec![];
+                for neighbour in neighbours {
+                    if let Some(value) = self.value(&neighbour, variable_name)? {
+                        values.push(value);
+                    }
+                }
+
+                if values.is_empty() {
+                    return match aggregation {
+                        Aggregation::Sum | Aggregation::Count => Ok(VariableValue::zero()),
+                        Aggregation::All => Ok(VariableValue::boolean(true)),
+                        Aggregation::Any => Ok(VariableValue::boolean(false)),
+                        _ => Err(FormulaError::Invalid(format!(
+                            "no {} have ${}",
+                            relation, variable_name
+                        ))),
+                    };
+                }
+
+                let results: Result<Vec<_>, _> = aggregate(values, *aggregation)
+                    .by_type
+                    .into_values()
+                    .collect();
+                let mut results = results.map_err(FormulaError::Invalid)?;
+                match results.len() {
+                    1 => Ok(results.remove(0)),
+                    _ => Ok(VariableValue::Mixed(results)),
+                }
+            }
+        }
+    }
+}
+
+fn apply(op: Op, lhs: VariableValue, rhs: VariableValue) -> Result<VariableValue, FormulaError> {
+    use VariableValue::{Number, Time as T};
+
+    let scale = |t: &Time, n: f64| Time::Minute((t.to_minutes() as f64 * n).round() as i32);
+
+    let value = match (op, &lhs, &rhs) {
+        (Op::Divide, _, Number(n)) if *n == 0.0 => {
+            return Err(FormulaError::Invalid("division by zero".to_string()))
+        }
+        (Op::Add, Number(a), Number(b)) => VariableValue::number(a + b),
+        (Op::Subtract, Number(a), Number(b)) => VariableValue::number(a - b),
+        (Op::Multiply, Number(a), Number(b)) => VariableValue::number(a * b),
+        (Op::Divide, Number(a), Number(b)) => VariableValue::number(a / b),
+        (Op::Add, T(a), T(b)) => VariableValue::time(a.clone() + b.clone()),
+        (Op::Subtract, T(a), T(b)) => VariableValue::time(a.clone() + -b.clone()),
+        (Op::Multiply, T(t), Number(n)) | (Op::Multiply, Number(n), T(t)) => {
+            VariableValue::time(scale(t, *n))
+        }
+        (Op::Divide, T(t), Number(n)) => VariableValue::time(scale(t, 1.0 / n)),
+        (Op::Divide, T(a), T(b)) if b.to_minutes() != 0 => {
+            VariableValue::number(a.to_minutes() as f64 / b.to_minutes() as f64)
+        }
+        _ => {
+            return Err(FormulaError::Invalid(format!(
+                "cannot {} {} and {}",
+                op,
+                lhs.typ(),
+                rhs.typ()
+            )))
+        }
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::NodeInfo;
+    use crate::Label;
+
+    fn node_value(label: &str, name: &str) -> VariableValue {
+        NodeInfo::parse(&Label::new(label))
+            .variables
+            .get(name)
+            .unwrap_or_else(|| panic!("no variable {}", name))
+            .value
+            .clone()
+    }
+
+    #[test]
+    fn it_only_treats_variable_references_as_formulas() {
+        assert!(Formula::parse("x+1").is_none());
+        assert!(Formula::parse("$5").is_none());
+        assert!(Formula::parse("$t*").is_none());
+        assert!(Formula::parse("$t*$people").is_some());
+        assert!(Formula::parse("sum(children.$cost)").is_some());
+    }
+
+    #[test]
+    fn it_evaluates_formulas_over_the_nodes_own_variables() {
+        let label = "task $t=2h $people=3 $effort=$t*$people $check=($people-1)/2";
+        assert_eq!(
+            node_value(label, "effort"),
+            VariableValue::time(Time::Hour(6))
+        );
+        assert_eq!(node_value(label, "check"), VariableValue::number(1.0));
+        assert_eq!(
+            node_value(
+                "risk $impact=3 $likelihood=0.5 $risk=$impact*$likelihood",
+                "risk"
+            ),
+            VariableValue::number(1.5)
+        );
+        assert_eq!(
+            node_value("padded $t=1d $total=$t+4h", "total"),
+            VariableValue::time(Time::Hour(12))
+        );
+    }
+
+    #[test]
+    fn it_reports_errors_in_place_of_the_value() {
+        assert_eq!(
+            node_value("loop $a=$b+1 $b=$a*2", "a"),
+            VariableValue::string("error: cycle $a -> $b -> $a")
+        );
+        assert_eq!(
+            node_value("missing $a=$nope*2", "a"),
+            VariableValue::string("error: unknown variable $nope")
+        );
+        assert_eq!(
+            node_value("typed $a=yes $b=$a*2", "b"),
+            VariableValue::string("error: cannot multiply text and numbers")
+        );
+    }
+
+    #[test]
+    fn it_leaves_graph_formulas_alone_without_a_graph() {
+        assert_eq!(
+            node_value(
+                "parent $total=sum(children.$cost) $double=$total*2",
+                "double"
+            ),
+            VariableValue::string("$total*2")
+        );
+    }
+
+    #[test]
+    fn it_aggregates_over_neighbours() {
+        let id = |s: &str| Id::new(s);
+        let vars = |label: &str| NodeInfo::parse(&Label::new(label)).variables;
+        let mut evaluator = Evaluator::new(
+            BTreeMap::from([
+                (id("root"), vars("$cost=sum(children.$cost)+1")),
+                (id("a"), vars("$cost=2")),
+                (id("b"), vars("$cost=max(children.$cost)")),
+                (id("c"), vars("$cost=5 $n=count(parents.$cost)")),
+            ]),
+            vec![
+                (id("root"), id("a")),
+                (id("root"), id("b")),
+                (id("b"), id("c")),
+            ],
+        );
+
+        assert_eq!(
+            evaluator.value(&id("root"), "cost"),
+            Ok(Some(VariableValue::number(8.0)))
+        );
+        assert_eq!(
+            evaluator.value(&id("c"), "n"),
+            Ok(Some(VariableValue::number(1.0)))
+        );
+    }
+
+    #[test]
+    fn it_detects_cycles_between_nodes() {
+        let id = |s: &str| Id::new(s);
+        let vars = |label: &str| NodeInfo::parse(&Label::new(label)).variables;
+        let mut evaluator = Evaluator::new(
+            BTreeMap::from([
+                (id("a"), vars("$x=sum(children.$x)")),
+                (id("b"), vars("$x=sum(children.$x)")),
+            ]),
+            vec![(id("a"), id("b")), (id("b"), id("a"))],
+        );
+
+        assert_eq!(
+            evaluator.value(&id("a"), "x"),
+            Err(FormulaError::Cycle(vec![
+                "a.$x".into(),
+                "b.$x".into(),
+                "a.$x".into()
+            ]))
+        );
+    }
+}
@@ -1,10 +1,12 @@
 use crate::command::GraphCommand;
 use crate::exporter::{Exporter, NodeHighlight};
-use crate::labels::NodeInfo;
+use crate::formula::{Evaluator, Formula};
+use crate::labels::{NodeInfo, Variables};
 use crate::pet::{GetVariableValue, PGraph};
 use crate::util::generate_hash;
 use crate::{CommandResult, Id, Label};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::{Display, Formatter};
@@ -17,7 +19,9 @@ const DAY: i32 = HOUR * 8;
 const MONTH: i32 = DAY * 20;
 const YEAR: i32 = DAY * 260;
 
-#[derive(Default)]
+/// serialising a graph captures its exact state, ids and all, unlike the JSON format which
+/// renumbers nodes when they are loaded.
+#[derive(Default, Serialize, Deserialize)]
 pub struct Graph {
     node_high_water: usize,
     edge_high_water: usize,
@@ -26,6 +30,9 @@ pub struct Graph {
     is_left_right: bool,
     current_search: Option<Label>,
     current_node: Option<Id>,
+    heatmap: Option<String>,
+    show_legend: bool,
+    subtotals: Option<String>,
 }
 
 impl Display for Graph {
@@ -39,12 +46,17 @@ impl Display for Graph {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct Node {
     id: Id,
     label: Label,
 }
 
 impl Node {
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
     pub fn label(&self) -> &Label {
         &self.label
     }
@@ -60,7 +72,7 @@ pub enum VariableValue {
 }
 
 impl VariableValue {
-    fn typ(&self) -> VariableType {
+    pub fn typ(&self) -> VariableType {
         match self {
             VariableValue::String(_) => VariableType::String,
             VariableValue::Number(_) => VariableType::Number,
@@ -72,12 +84,17 @@ impl VariableValue {
 }
 
 // An enum representing the type of variants in MyEnum
-#[derive(Debug, PartialEq, Eq, Hash)]
-enum VariableType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, strum_macros::Display)]
+pub enum VariableType {
+    #[strum(serialize = "text")]
     String,
+    #[strum(serialize = "numbers")]
     Number,
+    #[strum(serialize = "booleans")]
     Boolean,
+    #[strum(serialize = "times")]
     Time,
+    #[strum(serialize = "mixed")]
     Mixed,
 }
 
@@ -172,7 +189,7 @@ impl Add for VariableValue {
     }
 }
 
-impl<'a> Add<&'a VariableValue> for VariableValue {
+impl Add<&VariableValue> for VariableValue {
     type Output = VariableValue;
 
     fn add(self, rhs: &Self) -> Self::Output {
@@ -236,7 +253,10 @@ impl VariableValue {
 
     pub fn infer(value: impl Into<String>) -> Self {
         let value = value.into();
-        if value == "true" || value == "false" {
+        if Formula::parse(&value).is_some() {
+            // kept as text, and evaluated when needed; see `formula`.
+            VariableValue::String(value)
+        } else if value == "true" || value == "false" {
             VariableValue::Boolean(value.parse().unwrap())
         } else if let Ok(n) = value.parse() {
             VariableValue::Number(n)
@@ -266,6 +286,18 @@ impl VariableValue {
         VariableValue::Time(value)
     }
 
+    /// the value as a plain number, for things like scaling colours. Times are counted in
+    /// minutes; strings and mixed values have no numeric value.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            VariableValue::Number(n) => Some(*n),
+            VariableValue::Time(t) => Some(t.to_minutes() as f64),
+            VariableValue::Boolean(b) => Some(if *b { 1.0 } else { 0.0 }),
+            VariableValue::String(_) => None,
+            VariableValue::Mixed(_) => None,
+        }
+    }
+
     pub fn is_zero(&self) -> bool {
         match self {
             VariableValue::Number(n) => *n == 0.0,
@@ -472,6 +504,24 @@ impl Variable {
     }
 }
 
+/// How the graph is being looked at, as opposed to the graph itself; saved apart from the nodes
+/// and edges so that changing the view doesn't clutter diffs of the graph.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewState {
+    pub is_left_right: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_node: Option<Id>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_search: Option<Label>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heatmap: Option<String>,
+    pub show_legend: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subtotals: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
 struct Edge {
     id: Id,
     from: Id,
@@ -541,7 +591,7 @@ impl Graph {
         self.nodes
             .iter()
             .map(|node| {
-                let value = get_weights.get_weight(node);
+                let value = get_weights.get_weight_in_graph(self, node);
                 (node.id.clone(), value)
             })
             .collect()
@@ -600,19 +650,35 @@ impl Graph {
     }
 
     pub fn find_node_variable_value(&self, id: &Id, variable_name: &str) -> Option<VariableValue> {
-        let label = match self.find_node_label(id) {
-            Some(label) => label,
-            None => return None,
-        };
-        let NodeInfo {
-            label: _,
-            tags: _,
-            variables,
-            subgraph: _,
-        } = NodeInfo::parse(&label);
-        variables
-            .get(variable_name)
-            .map(|value| value.value.clone())
+        self.evaluator().value(id, variable_name).ok().flatten()
+    }
+
+    /// an evaluator for computed variables, which can follow formulas across the whole graph.
+    pub fn evaluator(&self) -> Evaluator {
+        let variables = self
+            .nodes
+            .iter()
+            .map(|node| (node.id.clone(), NodeInfo::parse(&node.label).variables))
+            .collect();
+        let edges = self
+            .edges
+            .iter()
+            .map(|edge| (edge.from.clone(), edge.to.clone()))
+            .collect();
+        Evaluator::new(variables, edges)
+    }
+
+    /// every node's variables, with formulas worked out.
+    pub fn evaluate_variables(&self) -> BTreeMap<Id, Variables> {
+        let mut evaluator = self.evaluator();
+        evaluator
+            .ids()
+            .into_iter()
+            .map(|id| {
+                let variables = evaluator.variables(&id);
+                (id, variables)
+            })
+            .collect()
     }
 
     pub fn highlight_search_results(&mut self, sub_label: Label) -> CommandResult {
@@ -640,6 +706,46 @@ impl Graph {
         CommandResult::new(msg)
     }
 
+    pub fn heatmap(&self) -> Option<&str> {
+        self.heatmap.as_deref()
+    }
+
+    pub fn set_heatmap(&mut self, variable_name: Option<String>) -> CommandResult {
+        let msg = match &variable_name {
+            Some(variable_name) => format!("Heatmap showing ${}", variable_name),
+            None => "Heatmap turned off".to_string(),
+        };
+        self.heatmap = variable_name;
+        CommandResult::new(msg)
+    }
+
+    pub fn subtotals(&self) -> Option<&str> {
+        self.subtotals.as_deref()
+    }
+
+    pub fn set_subtotals(&mut self, variable_name: Option<String>) -> CommandResult {
+        let msg = match &variable_name {
+            Some(variable_name) => {
+                format!("Subgraph labels showing subtotals of ${}", variable_name)
+            }
+            None => "Subgraph subtotals turned off".to_string(),
+        };
+        self.subtotals = variable_name;
+        CommandResult::new(msg)
+    }
+
+    pub fn show_legend(&self) -> bool {
+        self.show_legend
+    }
+
+    pub fn toggle_legend(&mut self) -> CommandResult {
+        self.show_legend = !self.show_legend;
+        CommandResult::new(format!(
+            "Legend {}",
+            if self.show_legend { "shown" } else { "hidden" }
+        ))
+    }
+
     pub fn set_direction(&mut self, is_left_right: bool) -> CommandResult {
         self.is_left_right = is_left_right;
         CommandResult::new(format!(
@@ -648,6 +754,36 @@ impl Graph {
         ))
     }
 
+    pub fn view_state(&self) -> ViewState {
+        ViewState {
+            is_left_right: self.is_left_right,
+            current_node: self.current_node.clone(),
+            current_search: self.current_search.clone(),
+            heatmap: self.heatmap.clone(),
+            show_legend: self.show_legend,
+            subtotals: self.subtotals.clone(),
+        }
+    }
+
+    /// restores a saved view; a selection which no longer exists is dropped.
+    pub fn set_view_state(&mut self, view_state: ViewState) {
+        let ViewState {
+            is_left_right,
+            current_node,
+            current_search,
+            heatmap,
+            show_legend,
+            subtotals,
+        } = view_state;
+
+        self.is_left_right = is_left_right;
+        self.current_node = current_node.filter(|id| self.find_node_idx(id).is_some());
+        self.current_search = current_search;
+        self.heatmap = heatmap;
+        self.show_legend = show_legend;
+        self.subtotals = subtotals;
+    }
+
     fn unlink_edge(&mut self, id: &Id) -> CommandResult {
         match self.find_edge_idx(id) {
             Some(idx) => {
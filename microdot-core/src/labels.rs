@@ -1,3 +1,4 @@
+use crate::formula::Evaluator;
 use crate::graph::Variable;
 use crate::hash::HashTag;
 use crate::Label;
@@ -29,6 +30,13 @@ impl Variables {
     pub fn iter(&self) -> std::slice::Iter<'_, Variable> {
         self.variables.iter()
     }
+
+    /// works out any formulas which only use this node's own variables; formulas which need
+    /// the rest of the graph are left as they are.
+    pub fn evaluate_local(self) -> Self {
+        let mut evaluator = Evaluator::standalone(self);
+        evaluator.variables(&Evaluator::standalone_id())
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -55,6 +63,7 @@ impl NodeInfo {
 
         let (tags, label) = extract_hashtags(base_label);
         let (variables, label) = extract_variables(label);
+        let variables = variables.evaluate_local();
 
         let subgraph: Option<HashTag> = tags
             .iter()
@@ -1,5 +1,7 @@
+pub mod aggregate;
 pub mod command;
 pub mod exporter;
+pub mod formula;
 pub mod graph;
 pub mod hash;
 pub mod labels;
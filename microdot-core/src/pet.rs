@@ -1,13 +1,23 @@
 //! petgraph functions.
+use crate::aggregate::{aggregate, Aggregated, Aggregation};
 use crate::graph::{Graph, Node, VariableValue};
+use crate::hash::HashTag;
 use crate::labels::NodeInfo;
 use crate::Id;
-use petgraph::algo::all_simple_paths;
+use petgraph::algo::{all_simple_paths, page_rank};
 use petgraph::prelude::NodeIndex;
-use std::collections::BTreeMap;
+use petgraph::visit::{Bfs, Reversed};
+use petgraph::Direction;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, VecDeque};
 
 pub trait GetVariableValue<T> {
     fn get_weight(&self, item: &T) -> Option<VariableValue>;
+
+    /// as `get_weight`, but with the whole graph to hand for values computed from neighbours.
+    fn get_weight_in_graph(&self, _graph: &Graph, item: &T) -> Option<VariableValue> {
+        self.get_weight(item)
+    }
 }
 
 impl<T, F: Fn(&T) -> Option<VariableValue>> GetVariableValue<T> for F {
@@ -151,14 +161,191 @@ pub fn find_cost(
     node_weights.values().flatten().cloned().sum()
 }
 
+/// How important a node is as a root cause; see `rank_nodes`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeRank {
+    pub id: Id,
+    /// the number of nodes reachable downstream of this one.
+    pub reach: usize,
+    /// how many shortest paths between other nodes pass through this one.
+    pub betweenness: f64,
+    /// PageRank over the reversed graph, so causes accumulate score from their effects.
+    pub page_rank: f64,
+}
+
+const PAGE_RANK_DAMPING: f64 = 0.85;
+const PAGE_RANK_ITERATIONS: usize = 100;
+
+/// Ranks every node by how much of the graph depends on it. Nodes are sorted by downstream reach,
+/// tie-breaking on betweenness, then PageRank, so the best candidates for a fix come first.
+pub fn rank_nodes(graph: &Graph) -> Vec<NodeRank> {
+    let pgraph = graph.to_petgraph();
+    let g = &pgraph.graph;
+
+    let betweenness = betweenness_centrality(g);
+    // edges run from cause to effect, so reverse them to let the score flow back to the causes.
+    let page_ranks = page_rank(Reversed(g), PAGE_RANK_DAMPING, PAGE_RANK_ITERATIONS);
+
+    let mut ranks: Vec<NodeRank> = g
+        .node_indices()
+        .filter_map(|idx| {
+            let id = pgraph.index_to_id.get(&idx)?.clone();
+
+            let mut reach = 0;
+            let mut bfs = Bfs::new(g, idx);
+            while let Some(next) = bfs.next(g) {
+                if next != idx {
+                    reach += 1;
+                }
+            }
+
+            Some(NodeRank {
+                id,
+                reach,
+                betweenness: betweenness[idx.index()],
+                page_rank: page_ranks[idx.index()],
+            })
+        })
+        .collect();
+
+    ranks.sort_by(|a, b| {
+        b.reach
+            .cmp(&a.reach)
+            .then(b.betweenness.total_cmp(&a.betweenness))
+            .then(b.page_rank.total_cmp(&a.page_rank))
+            .then(a.id.cmp(&b.id))
+    });
+
+    ranks
+}
+
+/// Brandes' algorithm for betweenness centrality on an unweighted, directed graph.
+fn betweenness_centrality(g: &petgraph::Graph<f64, f64>) -> Vec<f64> {
+    let n = g.node_count();
+    let mut centrality = vec![0.0f64; n];
+
+    for source in g.node_indices() {
+        let mut stack = vec![];
+        let mut predecessors: Vec<Vec<NodeIndex>> = vec![vec![]; n];
+        let mut paths = vec![0.0f64; n];
+        let mut distance: Vec<Option<usize>> = vec![None; n];
+        paths[source.index()] = 1.0;
+        distance[source.index()] = Some(0);
+
+        let mut queue = VecDeque::from([source]);
+        while let Some(v) = queue.pop_front() {
+            stack.push(v);
+            let dv = distance[v.index()].unwrap_or_default();
+            for w in g.neighbors_directed(v, Direction::Outgoing) {
+                if distance[w.index()].is_none() {
+                    distance[w.index()] = Some(dv + 1);
+                    queue.push_back(w);
+                }
+                if distance[w.index()] == Some(dv + 1) {
+                    paths[w.index()] += paths[v.index()];
+                    predecessors[w.index()].push(v);
+                }
+            }
+        }
+
+        let mut dependency = vec![0.0f64; n];
+        while let Some(w) = stack.pop() {
+            for v in &predecessors[w.index()] {
+                dependency[v.index()] +=
+                    (paths[v.index()] / paths[w.index()]) * (1.0 + dependency[w.index()]);
+            }
+            if w != source {
+                centrality[w.index()] += dependency[w.index()];
+            }
+        }
+    }
+
+    centrality
+}
+
+/// How to break a cost down into subtotals.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CostGrouping {
+    Subgraph,
+    HashTag,
+}
+
+/// Subtotals of a cost, keyed by subgraph or hashtag. Nodes with no subgraph, or no tags, are
+/// totalled under `None`. A node with several tags counts towards each of them.
+pub fn find_cost_by(
+    graph: &Graph,
+    get_weights: impl GetVariableValue<crate::graph::Node>,
+    grouping: CostGrouping,
+) -> BTreeMap<Option<HashTag>, VariableValue> {
+    find_values_by(graph, get_weights, grouping)
+        .into_iter()
+        .map(|(key, values)| (key, values.into_iter().sum()))
+        .collect()
+}
+
+/// Aggregates the values of every node, eg the median or maximum cost.
+pub fn find_aggregate(
+    graph: &Graph,
+    get_weights: impl GetVariableValue<crate::graph::Node>,
+    aggregation: Aggregation,
+) -> Aggregated {
+    let node_weights = graph.node_weights(get_weights);
+    aggregate(node_weights.into_values().flatten(), aggregation)
+}
+
+/// As `find_aggregate`, but aggregating each subgraph or hashtag separately.
+pub fn find_aggregate_by(
+    graph: &Graph,
+    get_weights: impl GetVariableValue<crate::graph::Node>,
+    grouping: CostGrouping,
+    aggregation: Aggregation,
+) -> BTreeMap<Option<HashTag>, Aggregated> {
+    find_values_by(graph, get_weights, grouping)
+        .into_iter()
+        .map(|(key, values)| (key, aggregate(values, aggregation)))
+        .collect()
+}
+
+fn find_values_by(
+    graph: &Graph,
+    get_weights: impl GetVariableValue<crate::graph::Node>,
+    grouping: CostGrouping,
+) -> BTreeMap<Option<HashTag>, Vec<VariableValue>> {
+    let node_weights = graph.node_weights(get_weights);
+
+    let mut groups: BTreeMap<Option<HashTag>, Vec<VariableValue>> = BTreeMap::new();
+    for (id, value) in node_weights {
+        let (Some(value), Some(label)) = (value, graph.find_node_label(&id)) else {
+            continue;
+        };
+
+        let NodeInfo { tags, subgraph, .. } = NodeInfo::parse(&label);
+        let keys: Vec<Option<HashTag>> = match grouping {
+            CostGrouping::Subgraph => vec![subgraph],
+            CostGrouping::HashTag if tags.is_empty() => vec![None],
+            CostGrouping::HashTag => tags.into_iter().map(Some).collect(),
+        };
+
+        for key in keys {
+            groups.entry(key).or_default().push(value.clone());
+        }
+    }
+
+    groups
+}
+
 pub struct CostCalculator {
     variable_name: String,
+    // every node's value, worked out together the first time a graph is seen so formulas are
+    // only evaluated once.
+    computed: OnceCell<BTreeMap<Id, Option<VariableValue>>>,
 }
 
 impl CostCalculator {
     pub fn new(variable_name: impl Into<String>) -> Self {
         CostCalculator {
             variable_name: variable_name.into(),
+            computed: OnceCell::new(),
         }
     }
 }
@@ -168,6 +355,22 @@ impl GetVariableValue<Node> for CostCalculator {
         let NodeInfo { variables, .. } = NodeInfo::parse(item.label());
         variables.get(&self.variable_name).map(|v| v.value.clone())
     }
+
+    fn get_weight_in_graph(&self, graph: &Graph, item: &Node) -> Option<VariableValue> {
+        let computed = self.computed.get_or_init(|| {
+            let mut evaluator = graph.evaluator();
+            evaluator
+                .ids()
+                .into_iter()
+                .map(|id| {
+                    // formulas which fail are left out of the analysis.
+                    let value = evaluator.value(&id, &self.variable_name).ok().flatten();
+                    (id, value)
+                })
+                .collect()
+        });
+        computed.get(item.id()).cloned().flatten()
+    }
 }
 
 #[cfg(test)]
@@ -288,6 +491,97 @@ pub mod tests {
         assert_eq!(longest.cost, None);
     }
 
+    #[test]
+    pub fn costs_can_be_grouped() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label("a $cost=1 #red #SG_ONE".to_string()));
+        graph.insert_node(Label("b $cost=2 #red #blue #SG_ONE".to_string()));
+        graph.insert_node(Label("c $cost=4 #SG_TWO".to_string()));
+        graph.insert_node(Label("d $cost=8".to_string()));
+        graph.insert_node(Label("e #blue".to_string()));
+
+        let by_subgraph = find_cost_by(&graph, CostCalculator::new("cost"), CostGrouping::Subgraph);
+        assert_eq!(
+            by_subgraph,
+            BTreeMap::from([
+                (None, VariableValue::number(8.0)),
+                (Some(HashTag::new("ONE")), VariableValue::number(3.0)),
+                (Some(HashTag::new("TWO")), VariableValue::number(4.0)),
+            ])
+        );
+
+        let by_tag = find_cost_by(&graph, CostCalculator::new("cost"), CostGrouping::HashTag);
+        assert_eq!(
+            by_tag,
+            BTreeMap::from([
+                (None, VariableValue::number(12.0)),
+                (Some(HashTag::new("#blue")), VariableValue::number(2.0)),
+                (Some(HashTag::new("#red")), VariableValue::number(3.0)),
+            ])
+        );
+    }
+
+    #[test]
+    pub fn costs_can_be_aggregated() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label("a $cost=1 #SG_ONE".to_string()));
+        graph.insert_node(Label("b $cost=2 #SG_ONE".to_string()));
+        graph.insert_node(Label("c $cost=6 #SG_ONE".to_string()));
+        graph.insert_node(Label("d $cost=1d".to_string()));
+        graph.insert_node(Label("e".to_string()));
+
+        let max = find_aggregate(&graph, CostCalculator::new("cost"), Aggregation::Max);
+        assert_eq!(max.to_string(), "numbers: 6; times: 1 day");
+
+        let by_subgraph = find_aggregate_by(
+            &graph,
+            CostCalculator::new("cost"),
+            CostGrouping::Subgraph,
+            Aggregation::Median,
+        );
+        let by_subgraph: Vec<_> = by_subgraph
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect();
+        assert_eq!(
+            by_subgraph,
+            vec![
+                (None, "1 day".to_string()),
+                (Some(HashTag::new("ONE")), "2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn ranks_root_causes_first() {
+        let mut graph = Graph::new();
+        // a root cause feeding a bottleneck which fans out to three symptoms
+        let root = graph.insert_node(Label("root".to_string())).0;
+        let neck = graph.insert_node(Label("bottleneck".to_string())).0;
+        let s1 = graph.insert_node(Label("symptom1".to_string())).0;
+        let s2 = graph.insert_node(Label("symptom2".to_string())).0;
+        let s3 = graph.insert_node(Label("symptom3".to_string())).0;
+        graph.link_edge(&root, &neck);
+        graph.link_edge(&neck, &s1);
+        graph.link_edge(&neck, &s2);
+        graph.link_edge(&neck, &s3);
+
+        let ranks = rank_nodes(&graph);
+        let ids: Vec<_> = ranks.iter().map(|r| r.id.clone()).collect();
+        assert_eq!(ids, vec![root.clone(), neck.clone(), s1, s2, s3]);
+
+        assert_eq!(ranks[0].reach, 4);
+        assert_eq!(ranks[1].reach, 3);
+        assert_eq!(ranks[4].reach, 0);
+
+        // every root-to-symptom path runs through the bottleneck
+        assert_eq!(ranks[0].betweenness, 0.0);
+        assert_eq!(ranks[1].betweenness, 3.0);
+
+        assert!(ranks[0].page_rank > ranks[1].page_rank);
+        assert!(ranks[1].page_rank > ranks[2].page_rank);
+    }
+
     #[test]
     pub fn handles_empty_cost_nodes_gracefully() {
         let mut graph = Graph::new();
@@ -0,0 +1,214 @@
+use crate::graphviz::{render_svg, DisplayMode, GraphVizExporter};
+use crate::repl::repl;
+use crate::session::Session;
+use crate::storage::FileStore;
+use crate::Interaction;
+use anyhow::{Context, Result};
+use askama::Template;
+use microdot_core::graph::Graph;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc;
+
+/// The graph as it stood after one command of a script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub command: String,
+    /// whatever the command printed, like "(inserted node n0: 'start')".
+    pub output: String,
+    pub dot: String,
+}
+
+/// Feeds a script to the repl, taking a picture of the graph after each command.
+struct Recorder {
+    lines: VecDeque<String>,
+    graph: Arc<RwLock<Graph>>,
+    frames: Vec<Frame>,
+    pending: Option<(String, String)>,
+}
+
+impl Recorder {
+    fn finish_frame(&mut self) {
+        if let Some((command, output)) = self.pending.take() {
+            let graph = self.graph.read().unwrap();
+            let dot = GraphVizExporter::new(DisplayMode::Interactive).export_dot(&graph);
+            self.frames.push(Frame {
+                command,
+                output: output.trim_end().to_string(),
+                dot,
+            });
+        }
+    }
+}
+
+impl Interaction for Recorder {
+    fn read(&mut self, _prompt: &str) -> rustyline::Result<String> {
+        self.finish_frame();
+        match self.lines.pop_front() {
+            Some(line) => {
+                self.pending = Some((line.clone(), String::new()));
+                Ok(line)
+            }
+            None => Err(rustyline::error::ReadlineError::Eof),
+        }
+    }
+
+    fn add_history<S: AsRef<str> + Into<String>>(&mut self, _history: S) -> bool {
+        true
+    }
+
+    fn log<S: AsRef<str> + Into<String>>(&mut self, message: S) {
+        if let Some((_, output)) = &mut self.pending {
+            output.push_str(message.as_ref());
+            output.push('\n');
+        }
+    }
+
+    fn should_compile(&self) -> bool {
+        false
+    }
+}
+
+/// runs each line of a script through the repl, as `compile_input_string_content` does, and
+/// returns a frame for every command. Blank lines are skipped.
+pub fn record_frames(script: &str, scratch_json: &Path) -> Result<Vec<Frame>> {
+    let graph = Arc::new(RwLock::new(Graph::new()));
+    let mut recorder = Recorder {
+        lines: script
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_string())
+            .collect(),
+        graph: graph.clone(),
+        frames: vec![],
+        pending: None,
+    };
+
+    let (reload_tx, _reload_rx) = mpsc::unbounded_channel();
+    let (store, name) = FileStore::for_file(scratch_json);
+    let session = Session::new(
+        graph,
+        scratch_json.to_path_buf(),
+        Arc::new(store),
+        name,
+        reload_tx,
+    );
+    repl(&mut recorder, &session)?;
+    recorder.finish_frame();
+
+    Ok(recorder.frames)
+}
+
+#[derive(Template)]
+#[template(path = "animation.html")]
+struct AnimationPage {
+    title: String,
+    frames: Vec<FrameViewModel>,
+}
+
+struct FrameViewModel {
+    command: String,
+    output: String,
+    svg: String,
+}
+
+/// a self-contained html slideshow of the frames, given each frame's svg.
+pub fn animation_html(title: &str, frames: &[Frame], svgs: Vec<String>) -> Result<String> {
+    let frames = frames
+        .iter()
+        .zip(svgs)
+        .map(|(frame, svg)| FrameViewModel {
+            command: frame.command.clone(),
+            output: frame.output.clone(),
+            svg: inline_svg(&svg).to_string(),
+        })
+        .collect();
+
+    let page = AnimationPage {
+        title: title.to_string(),
+        frames,
+    };
+    Ok(page.render()?)
+}
+
+/// drops the xml declaration and doctype graphviz puts before the `<svg>` element, so the svg
+/// can sit inside an html page.
+fn inline_svg(svg: &str) -> &str {
+    svg.find("<svg").map(|start| &svg[start..]).unwrap_or(svg)
+}
+
+/// renders a build-up animation of a script, like `examples/fellowship.txt`, to an html file
+/// next to it, and returns the path of the page.
+pub fn animate(script_file: &Path, out: Option<PathBuf>) -> Result<PathBuf> {
+    let script = std::fs::read_to_string(script_file)
+        .with_context(|| format!("reading script {}", script_file.display()))?;
+    let title = script_file
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // the script's graph, and the diagrams the repl renders of it, are thrown away afterwards.
+    let scratch = tempfile::tempdir().context("making a scratch directory")?;
+    let scratch_json = scratch.path().join(format!("{}.animation.json", title));
+
+    let frames = record_frames(&script, &scratch_json)?;
+    let svgs = frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            render_svg(&frame.dot)
+                .with_context(|| format!("rendering step {}: {}", i + 1, frame.command))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let out = out.unwrap_or_else(|| script_file.with_extension("animation.html"));
+    std::fs::write(&out, animation_html(&title, &frames, svgs)?)
+        .with_context(|| format!("writing animation {}", out.display()))?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_a_frame_per_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = "i start\n\ni finish\nl n0 n1\n/fin\n";
+        let frames = record_frames(script, &dir.path().join("script.json")).unwrap();
+
+        let commands: Vec<_> = frames.iter().map(|f| f.command.as_str()).collect();
+        assert_eq!(commands, vec!["i start", "i finish", "l n0 n1", "/fin"]);
+        assert_eq!(frames[0].output, "(inserted node n0: 'start')");
+        assert!(!frames[0].dot.contains("finish"));
+        assert!(frames[1].dot.contains("finish"));
+        assert!(!frames[1].dot.contains(r#""n0" -> "n1""#));
+        assert!(frames[2].dot.contains(r#""n0" -> "n1""#));
+    }
+
+    #[test]
+    fn builds_a_slideshow() {
+        let frames = vec![
+            Frame {
+                command: "i a <b>".to_string(),
+                output: "(inserted node n0: 'a <b>')".to_string(),
+                dot: String::new(),
+            },
+            Frame {
+                command: "lr".to_string(),
+                output: String::new(),
+                dot: String::new(),
+            },
+        ];
+        let svgs = vec![
+            "<?xml version=\"1.0\"?>\n<svg id=\"one\"></svg>".to_string(),
+            "<svg id=\"two\"></svg>".to_string(),
+        ];
+
+        let html = animation_html("story", &frames, svgs).unwrap();
+        assert!(html.contains("<svg id=\"one\"></svg>"));
+        assert!(!html.contains("<?xml"));
+        assert!(html.contains("i a &lt;b&gt;"));
+        assert!(html.contains("<title>story</title>"));
+    }
+}
@@ -0,0 +1,337 @@
+use crate::parser::parse_line;
+use crate::session::Session;
+use crate::Command;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use microdot_core::command::GraphCommand;
+use microdot_core::exporter::{Exporter, NodeHighlight};
+use microdot_core::graph::{Graph, VariableValue};
+use microdot_core::labels::NodeInfo;
+use microdot_core::pet::{find_longest_path, CostCalculator};
+use microdot_core::{Id, Label, Line};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// the JSON api, under `/api`, for scripts and bots to read and change the graph.
+pub fn routes(session: Session) -> Router {
+    Router::new()
+        .route("/api/commands", post(post_command))
+        .route("/api/nodes", get(list_nodes))
+        .route("/api/nodes/:id", get(get_node))
+        .route("/api/analysis/crit", get(critical_path))
+        .with_state(session)
+}
+
+/// A command sent to microdot, either as it would be typed at the repl, like
+/// `{"cmd": "aft n1 foo"}`, or as it's written to the event log, like
+/// `{"command": "insert_node", "label": "foo"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum CommandRequest {
+    Text { cmd: String },
+    Structured(GraphCommand),
+}
+
+impl CommandRequest {
+    /// the change to the graph asked for; repl commands which don't change the graph, like
+    /// searches or analyses, can't be sent.
+    pub fn graph_command(self) -> Result<GraphCommand, String> {
+        match self {
+            CommandRequest::Structured(command) => Ok(command),
+            CommandRequest::Text { cmd } => match parse_line(Line::new(cmd.clone())) {
+                Command::GraphCommand(command) => Ok(command),
+                Command::ParseError { .. } => Err(format!("could not understand '{}'", cmd)),
+                _ => Err(format!(
+                    "'{}' doesn't change the graph; only commands which do can be sent",
+                    cmd
+                )),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CommandResponse {
+    pub result: String,
+    /// a summary of the graph afterwards, like "Graph: 3 nodes, 2 edges".
+    pub graph: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub(crate) type ApiError = (StatusCode, Json<ErrorResponse>);
+
+pub(crate) fn api_error(status: StatusCode, error: impl ToString) -> ApiError {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.to_string(),
+        }),
+    )
+}
+
+/// A node as the api describes it, with its label taken apart.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NodeView {
+    pub id: Id,
+    pub label: Label,
+    /// the label without its tags and variables.
+    pub text: String,
+    pub tags: Vec<String>,
+    pub subgraph: Option<String>,
+    /// the node's variables, with any formulas worked out.
+    pub variables: BTreeMap<String, Value>,
+    pub predecessors: Vec<Id>,
+    pub successors: Vec<Id>,
+}
+
+#[derive(Default)]
+struct NodeViewExporter {
+    nodes: Vec<(Id, Label)>,
+    edges: Vec<(Id, Id)>,
+}
+
+impl Exporter for NodeViewExporter {
+    fn set_direction(&mut self, _is_left_right: bool) {}
+
+    fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight) {
+        self.nodes.push((id.clone(), label.clone()));
+    }
+
+    fn add_edge(&mut self, _id: &Id, from: &Id, to: &Id) {
+        self.edges.push((from.clone(), to.clone()));
+    }
+}
+
+/// every node in the graph, in the order they were added.
+pub fn node_views(graph: &Graph) -> Vec<NodeView> {
+    let mut exporter = NodeViewExporter::default();
+    graph.export(&mut exporter);
+    let mut variables = graph.evaluate_variables();
+
+    exporter
+        .nodes
+        .into_iter()
+        .map(|(id, label)| {
+            let info = NodeInfo::parse(&label);
+            let variables = variables
+                .remove(&id)
+                .map(|variables| {
+                    variables
+                        .iter()
+                        .map(|v| (v.name.clone(), variable_json(&v.value)))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let predecessors = exporter
+                .edges
+                .iter()
+                .filter(|(_, to)| to == &id)
+                .map(|(from, _)| from.clone())
+                .collect();
+            let successors = exporter
+                .edges
+                .iter()
+                .filter(|(from, _)| from == &id)
+                .map(|(_, to)| to.clone())
+                .collect();
+            NodeView {
+                id,
+                label,
+                text: info.label,
+                tags: info.tags.iter().map(|tag| tag.to_string()).collect(),
+                subgraph: info.subgraph.map(|subgraph| subgraph.to_string()),
+                variables,
+                predecessors,
+                successors,
+            }
+        })
+        .collect()
+}
+
+/// numbers and booleans as themselves; anything else, like times, as text.
+fn variable_json(value: &VariableValue) -> Value {
+    match value {
+        VariableValue::Number(n) => serde_json::json!(n),
+        VariableValue::Boolean(b) => Value::Bool(*b),
+        other => Value::String(other.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CriticalPathQuery {
+    pub var: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CriticalPathStep {
+    pub id: Id,
+    pub label: Label,
+    pub value: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CriticalPathView {
+    pub variable: String,
+    pub path: Vec<CriticalPathStep>,
+    pub cost: Option<Value>,
+    pub length: usize,
+}
+
+async fn post_command(
+    State(session): State<Session>,
+    Json(request): Json<CommandRequest>,
+) -> Result<Json<CommandResponse>, ApiError> {
+    run_command(session, "api", request).await.map(Json)
+}
+
+/// applies a command from the api or a web page on behalf of `by`, and describes the result.
+pub(crate) async fn run_command(
+    session: Session,
+    by: &str,
+    request: CommandRequest,
+) -> Result<CommandResponse, ApiError> {
+    let command = request
+        .graph_command()
+        .map_err(|e| api_error(StatusCode::BAD_REQUEST, e))?;
+    if let Some(reason) = session.read_only() {
+        return Err(api_error(StatusCode::FORBIDDEN, reason));
+    }
+
+    // saving and rendering touch the disc, and can take a while for big graphs.
+    let by = by.to_string();
+    tokio::task::spawn_blocking(move || {
+        let result = session.apply(&by, command)?;
+        let graph = session.graph.read().unwrap().to_string();
+        Ok::<_, anyhow::Error>(CommandResponse {
+            result: result.to_string(),
+            graph,
+        })
+    })
+    .await
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+async fn list_nodes(State(session): State<Session>) -> Json<Vec<NodeView>> {
+    Json(node_views(&session.graph.read().unwrap()))
+}
+
+async fn get_node(
+    State(session): State<Session>,
+    Path(id): Path<String>,
+) -> Result<Json<NodeView>, ApiError> {
+    let id = Id::new(id);
+    node_views(&session.graph.read().unwrap())
+        .into_iter()
+        .find(|node| node.id == id)
+        .map(Json)
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, format!("no node {}", id)))
+}
+
+async fn critical_path(
+    State(session): State<Session>,
+    Query(query): Query<CriticalPathQuery>,
+) -> Json<CriticalPathView> {
+    let graph = session.graph.read().unwrap();
+    let longest_path = find_longest_path(&graph, CostCalculator::new(query.var.clone()));
+    let path = longest_path
+        .ids
+        .iter()
+        .filter_map(|id| {
+            Some(CriticalPathStep {
+                id: id.clone(),
+                label: graph.find_node_label(id)?,
+                value: graph
+                    .find_node_variable_value(id, &query.var)
+                    .map(|value| variable_json(&value)),
+            })
+        })
+        .collect();
+
+    Json(CriticalPathView {
+        variable: query.var,
+        path,
+        cost: longest_path.cost.as_ref().map(variable_json),
+        length: longest_path.ids.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::test_session;
+
+    fn session(dir: &std::path::Path) -> Session {
+        test_session(&dir.join("api.json"), None).0
+    }
+
+    fn text(cmd: &str) -> Json<CommandRequest> {
+        Json(CommandRequest::Text {
+            cmd: cmd.to_string(),
+        })
+    }
+
+    #[test]
+    fn accepts_repl_text_or_structured_commands() {
+        let text: CommandRequest = serde_json::from_str(r#"{"cmd": "i start"}"#).unwrap();
+        let structured: CommandRequest =
+            serde_json::from_str(r#"{"command": "insert_node", "label": "start"}"#).unwrap();
+        assert_eq!(text.graph_command(), structured.graph_command());
+
+        let search = CommandRequest::Text {
+            cmd: "/start".to_string(),
+        };
+        assert!(search.graph_command().is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_change_and_save_the_graph() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = session(dir.path());
+
+        let response = post_command(State(session.clone()), text("i start $t=2"))
+            .await
+            .unwrap();
+        assert_eq!(response.result, "inserted node n0: 'start $t=2'");
+        let response = post_command(State(session.clone()), text("aft n0 finish $t=3 #done"))
+            .await
+            .unwrap();
+        assert_eq!(response.graph, "Graph: 2 nodes, 1 edges");
+        assert!(std::fs::read_to_string(dir.path().join("api.json"))
+            .unwrap()
+            .contains("finish"));
+
+        let Json(nodes) = list_nodes(State(session.clone())).await;
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].text, "finish");
+        assert_eq!(nodes[1].tags, vec!["#done"]);
+        assert_eq!(nodes[1].variables["t"], serde_json::json!(3.0));
+        assert_eq!(nodes[1].predecessors, vec![Id::new("n0")]);
+
+        let Json(node) = get_node(State(session.clone()), Path("n0".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(node.successors, vec![Id::new("n1")]);
+        let (status, _) = get_node(State(session.clone()), Path("n9".to_string()))
+            .await
+            .unwrap_err();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let Json(crit) = critical_path(
+            State(session),
+            Query(CriticalPathQuery {
+                var: "t".to_string(),
+            }),
+        )
+        .await;
+        assert_eq!(crit.length, 2);
+        assert_eq!(crit.cost, Some(serde_json::json!(5.0)));
+    }
+}
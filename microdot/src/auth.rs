@@ -0,0 +1,232 @@
+use crate::api::api_error;
+use axum::extract::{Query, Request, State};
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+
+/// What a request to the web server may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+    Read,
+    Write,
+}
+
+pub(crate) const READ_ONLY: &str = "this token can only look at the graph";
+
+const CROSS_ORIGIN: &str = "pages from other sites can't connect to the graph";
+
+/// The tokens the web server asks for. Without any, whoever can reach the server may change the
+/// graphs; with them, requests must bring the read/write token to change a graph, or the
+/// read-only token to look at it.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    token: Option<String>,
+    read_token: Option<String>,
+}
+
+/// `?token=`, as the pages pass it on to the server.
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    pub token: Option<String>,
+}
+
+impl Access {
+    pub fn new(token: Option<String>, read_token: Option<String>) -> Self {
+        Self { token, read_token }
+    }
+
+    /// whether anyone at all may change the graphs.
+    pub fn is_open(&self) -> bool {
+        self.token.is_none() && self.read_token.is_none()
+    }
+
+    /// what a request bringing `presented` may do, if anything.
+    pub fn permission(&self, presented: Option<&str>) -> Option<Permission> {
+        if self.is_open() {
+            return Some(Permission::Write);
+        }
+        let brings = |token: &Option<String>| match (token, presented) {
+            (Some(token), Some(presented)) => same(token, presented),
+            _ => false,
+        };
+        if brings(&self.token) {
+            Some(Permission::Write)
+        } else if brings(&self.read_token) {
+            Some(Permission::Read)
+        } else {
+            None
+        }
+    }
+}
+
+/// compares every byte, so how long it takes doesn't give away how much of a token was right.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// whether a request comes from a page this server served, or from something other than a
+/// browser, which sends no `Origin`. Browsers let any page open a websocket to any server, so
+/// without this, any site the user visits could change their graph.
+pub fn same_origin(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let origin = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map(|(_scheme, host)| host);
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok());
+    match (origin, host) {
+        (Some(origin), Some(host)) => origin.eq_ignore_ascii_case(host),
+        _ => false,
+    }
+}
+
+/// middleware which turns away requests from other sites' pages; see `same_origin`.
+pub async fn require_same_origin(request: Request, next: Next) -> Response {
+    if same_origin(request.headers()) {
+        next.run(request).await
+    } else {
+        api_error(StatusCode::FORBIDDEN, CROSS_ORIGIN).into_response()
+    }
+}
+
+/// the token a request brings, from an `Authorization: Bearer` header or `?token=`.
+fn presented_token(request: &Request) -> Option<String> {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    bearer.or_else(|| {
+        Query::<TokenQuery>::try_from_uri(request.uri())
+            .ok()
+            .and_then(|Query(query)| query.token)
+    })
+}
+
+/// middleware which turns away requests without the token they need; reading needs either
+/// token, anything else the read/write one. What the request may do is left in its extensions,
+/// for the websocket, which reads and writes over one connection.
+pub async fn require_token(
+    State(access): State<Access>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let needed = match *request.method() {
+        Method::GET | Method::HEAD => Permission::Read,
+        _ => Permission::Write,
+    };
+    match access.permission(presented_token(&request).as_deref()) {
+        Some(permission) if permission >= needed => {
+            request.extensions_mut().insert(permission);
+            next.run(request).await
+        }
+        Some(_) => api_error(StatusCode::FORBIDDEN, READ_ONLY).into_response(),
+        None => api_error(
+            StatusCode::UNAUTHORIZED,
+            "a token is needed, as ?token= or an Authorization: Bearer header",
+        )
+        .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn access() -> Access {
+        Access::new(Some("rw".to_string()), Some("ro".to_string()))
+    }
+
+    #[test]
+    fn tokens_give_their_own_permissions() {
+        assert_eq!(Access::default().permission(None), Some(Permission::Write));
+        assert_eq!(access().permission(Some("rw")), Some(Permission::Write));
+        assert_eq!(access().permission(Some("ro")), Some(Permission::Read));
+        assert_eq!(access().permission(Some("r")), None);
+        assert_eq!(access().permission(None), None);
+
+        let look_only = Access::new(None, Some("ro".to_string()));
+        assert_eq!(look_only.permission(Some("ro")), Some(Permission::Read));
+        assert_eq!(look_only.permission(None), None);
+    }
+
+    #[test]
+    fn only_pages_from_this_server_are_the_same_origin() {
+        let from = |origin: Option<&str>| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::HOST, "localhost:7777".parse().unwrap());
+            if let Some(origin) = origin {
+                headers.insert(header::ORIGIN, origin.parse().unwrap());
+            }
+            same_origin(&headers)
+        };
+
+        assert!(from(None));
+        assert!(from(Some("http://localhost:7777")));
+        assert!(!from(Some("https://example.com")));
+        assert!(!from(Some("http://localhost:8080")));
+        assert!(!from(Some("null")));
+    }
+
+    #[tokio::test]
+    async fn requests_need_a_token_which_allows_them() {
+        let app = Router::new()
+            .route(
+                "/graph",
+                get(|| async { "graph" }).post(|| async { "changed" }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                access(),
+                require_token,
+            ));
+        let status = |request: Request| {
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
+        let request = |method: &str, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert_eq!(
+            status(request("GET", "/graph")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(request("GET", "/graph?token=ro")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(request("POST", "/graph?token=ro")).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(request("POST", "/graph?token=rw")).await,
+            StatusCode::OK
+        );
+
+        let mut bearer = request("POST", "/graph");
+        bearer
+            .headers_mut()
+            .insert(header::AUTHORIZATION, "Bearer rw".parse().unwrap());
+        assert_eq!(status(bearer).await, StatusCode::OK);
+    }
+}
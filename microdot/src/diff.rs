@@ -0,0 +1,406 @@
+use crate::graphviz::export_diff_dot;
+use crate::json::{JsonEdge, JsonGraph};
+use anyhow::Result;
+use microdot_core::labels::NodeInfo;
+use microdot_core::{Id, Label};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// How the nodes of two versions of a graph are paired up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Matching {
+    /// nodes with the same id are the same node.
+    Ids,
+    /// nodes with the same label, or failing that the most similar label, are the same node; for
+    /// when the ids have been renumbered.
+    Labels,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    Unchanged,
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A node from either graph. Removed nodes keep their old id; the rest have their new one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeDiff {
+    pub id: Id,
+    pub label: Label,
+    pub status: DiffStatus,
+    /// what happened to a changed node, like "#urgent added".
+    pub changes: Vec<String>,
+}
+
+/// An edge from either graph, between two of the diff's `nodes`, by position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EdgeDiff {
+    pub from: usize,
+    pub to: usize,
+    pub status: DiffStatus,
+}
+
+/// Both versions of a graph laid over each other.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphDiff {
+    pub nodes: Vec<NodeDiff>,
+    pub edges: Vec<EdgeDiff>,
+}
+
+/// how much two labels need to share, as a proportion of their words, to be taken for the same
+/// node when matching by label.
+const SIMILAR_LABELS: f64 = 0.5;
+
+/// compares two graph documents.
+pub fn diff(old: &str, new: &str, matching: Matching) -> Result<GraphDiff> {
+    let old = JsonGraph::parse(old)?;
+    let new = JsonGraph::parse(new)?;
+    let matches = match matching {
+        Matching::Ids => match_ids(&old, &new),
+        Matching::Labels => match_labels(&old, &new),
+    };
+
+    let mut nodes = vec![];
+    let mut old_index = HashMap::new();
+    let mut new_index = HashMap::new();
+    for (n, node) in new.nodes.iter().enumerate() {
+        let (status, changes) = match matches.get(&n) {
+            Some(&o) => {
+                old_index.insert(old.nodes[o].id.clone(), nodes.len());
+                let changes = node_changes(&old.nodes[o].label, &node.label);
+                if changes.is_empty() {
+                    (DiffStatus::Unchanged, changes)
+                } else {
+                    (DiffStatus::Changed, changes)
+                }
+            }
+            None => (DiffStatus::Added, vec![]),
+        };
+        new_index.insert(node.id.clone(), nodes.len());
+        nodes.push(NodeDiff {
+            id: node.id.clone(),
+            label: node.label.clone(),
+            status,
+            changes,
+        });
+    }
+
+    let matched: HashSet<usize> = matches.values().copied().collect();
+    for (o, node) in old.nodes.iter().enumerate() {
+        if !matched.contains(&o) {
+            old_index.insert(node.id.clone(), nodes.len());
+            nodes.push(NodeDiff {
+                id: node.id.clone(),
+                label: node.label.clone(),
+                status: DiffStatus::Removed,
+                changes: vec![],
+            });
+        }
+    }
+
+    let ends = |edges: &[JsonEdge], index: &HashMap<Id, usize>| -> Vec<(usize, usize)> {
+        edges
+            .iter()
+            .filter_map(|edge| Some((*index.get(&edge.from)?, *index.get(&edge.to)?)))
+            .collect()
+    };
+    let old_edges = ends(&old.edges, &old_index);
+    let new_edges = ends(&new.edges, &new_index);
+    let old_set: HashSet<_> = old_edges.iter().copied().collect();
+    let new_set: HashSet<_> = new_edges.iter().copied().collect();
+
+    let mut edges: Vec<_> = new_edges
+        .iter()
+        .map(|&(from, to)| EdgeDiff {
+            from,
+            to,
+            status: if old_set.contains(&(from, to)) {
+                DiffStatus::Unchanged
+            } else {
+                DiffStatus::Added
+            },
+        })
+        .collect();
+    edges.extend(
+        old_edges
+            .iter()
+            .filter(|edge| !new_set.contains(edge))
+            .map(|&(from, to)| EdgeDiff {
+                from,
+                to,
+                status: DiffStatus::Removed,
+            }),
+    );
+
+    Ok(GraphDiff { nodes, edges })
+}
+
+/// pairs each new node, by position, with the old node it was.
+pub(crate) fn match_ids(old: &JsonGraph, new: &JsonGraph) -> HashMap<usize, usize> {
+    let old_ids: HashMap<&Id, usize> = old
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(o, node)| (&node.id, o))
+        .collect();
+    new.nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(n, node)| Some((n, *old_ids.get(&node.id)?)))
+        .collect()
+}
+
+pub(crate) fn match_labels(old: &JsonGraph, new: &JsonGraph) -> HashMap<usize, usize> {
+    let mut matches = HashMap::new();
+    let mut taken = HashSet::new();
+    for (n, node) in new.nodes.iter().enumerate() {
+        let same =
+            (0..old.nodes.len()).find(|o| !taken.contains(o) && old.nodes[*o].label == node.label);
+        if let Some(o) = same {
+            taken.insert(o);
+            matches.insert(n, o);
+        }
+    }
+
+    let mut candidates = vec![];
+    for (n, node) in new.nodes.iter().enumerate() {
+        if matches.contains_key(&n) {
+            continue;
+        }
+        for (o, old_node) in old.nodes.iter().enumerate() {
+            if taken.contains(&o) {
+                continue;
+            }
+            let score = similarity(&old_node.label, &node.label);
+            if score >= SIMILAR_LABELS {
+                candidates.push((score, n, o));
+            }
+        }
+    }
+
+    candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+    for (_, n, o) in candidates {
+        if !matches.contains_key(&n) && taken.insert(o) {
+            matches.insert(n, o);
+        }
+    }
+    matches
+}
+
+/// the proportion of words two labels share, ignoring tags and variables.
+fn similarity(a: &Label, b: &Label) -> f64 {
+    let words = |label: &Label| -> HashSet<String> {
+        NodeInfo::parse(label)
+            .label
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_string())
+            .collect()
+    };
+    let (a, b) = (words(a), words(b));
+    let all = a.union(&b).count();
+    if all == 0 {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / all as f64
+}
+
+fn node_changes(old: &Label, new: &Label) -> Vec<String> {
+    let (old, new) = (NodeInfo::parse(old), NodeInfo::parse(new));
+    let mut changes = vec![];
+
+    if old.label != new.label {
+        changes.push(format!("relabelled '{}' to '{}'", old.label, new.label));
+    }
+
+    for variable in new.variables.iter() {
+        match old.variables.get(&variable.name) {
+            None => changes.push(format!("${} added", variable)),
+            Some(was) if was.value != variable.value => changes.push(format!(
+                "${} changed from {} to {}",
+                variable.name, was.value, variable.value
+            )),
+            _ => {}
+        }
+    }
+    for variable in old.variables.iter() {
+        if new.variables.get(&variable.name).is_none() {
+            changes.push(format!("${} removed", variable));
+        }
+    }
+
+    for tag in new.tags.iter().filter(|tag| !old.tags.contains(tag)) {
+        changes.push(format!("{} added", tag));
+    }
+    for tag in old.tags.iter().filter(|tag| !new.tags.contains(tag)) {
+        changes.push(format!("{} removed", tag));
+    }
+
+    match (&old.subgraph, &new.subgraph) {
+        (None, Some(to)) => changes.push(format!("moved into subgraph {}", to)),
+        (Some(from), None) => changes.push(format!("moved out of subgraph {}", from)),
+        (Some(from), Some(to)) if from != to => {
+            changes.push(format!("moved from subgraph {} to {}", from, to))
+        }
+        _ => {}
+    }
+
+    changes
+}
+
+impl GraphDiff {
+    pub fn is_empty(&self) -> bool {
+        self.nodes
+            .iter()
+            .all(|node| node.status == DiffStatus::Unchanged)
+            && self
+                .edges
+                .iter()
+                .all(|edge| edge.status == DiffStatus::Unchanged)
+    }
+
+    /// both graphs in one diagram; added parts green, removed parts red, and changed nodes amber.
+    pub fn to_dot(&self) -> String {
+        // a removed node may share its id with a new one, so they're drawn under other names.
+        let key = |node: &NodeDiff| match node.status {
+            DiffStatus::Removed => format!("removed_{}", node.id),
+            _ => node.id.to_string(),
+        };
+        let nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|node| (key(node), node.label.to_string(), node.status))
+            .collect();
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                (
+                    nodes[edge.from].0.clone(),
+                    nodes[edge.to].0.clone(),
+                    edge.status,
+                )
+            })
+            .collect();
+        export_diff_dot(&nodes, &edges)
+    }
+}
+
+impl Display for GraphDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no differences");
+        }
+
+        for node in &self.nodes {
+            match node.status {
+                DiffStatus::Unchanged => {}
+                DiffStatus::Added => writeln!(f, "+ {}: '{}'", node.id, node.label)?,
+                DiffStatus::Removed => writeln!(f, "- {}: '{}'", node.id, node.label)?,
+                DiffStatus::Changed => {
+                    for change in &node.changes {
+                        writeln!(f, "~ {}: {}", node.id, change)?;
+                    }
+                }
+            }
+        }
+
+        for edge in &self.edges {
+            let sign = match edge.status {
+                DiffStatus::Added => "+",
+                DiffStatus::Removed => "-",
+                _ => continue,
+            };
+            let (from, to) = (&self.nodes[edge.from], &self.nodes[edge.to]);
+            writeln!(
+                f,
+                "{} {} -> {}: '{}' -> '{}'",
+                sign, from.id, to.id, from.label, to.label
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::test_graph;
+
+    #[test]
+    fn lists_what_changed() {
+        let old = test_graph(
+            &[
+                ("n0", "start $cost=3 #urgent"),
+                ("n1", "middle"),
+                ("n2", "end"),
+            ],
+            &[("n0", "n1"), ("n1", "n2")],
+        );
+        let new = test_graph(
+            &[
+                ("n0", "begin $cost=5 #late"),
+                ("n1", "middle"),
+                ("n3", "new"),
+            ],
+            &[("n0", "n1"), ("n1", "n3")],
+        );
+
+        let diff = diff(&old, &new, Matching::Ids).unwrap();
+        assert_eq!(
+            diff.to_string(),
+            [
+                "~ n0: relabelled 'start' to 'begin'",
+                "~ n0: $cost changed from 3 to 5",
+                "~ n0: #late added",
+                "~ n0: #urgent removed",
+                "+ n3: 'new'",
+                "- n2: 'end'",
+                "+ n1 -> n3: 'middle' -> 'new'",
+                "- n1 -> n2: 'middle' -> 'end'",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn matches_renumbered_nodes_by_label() {
+        let old = test_graph(
+            &[("n0", "a"), ("n1", "gone"), ("n2", "the ring goes south")],
+            &[("n0", "n2")],
+        );
+        let new = test_graph(
+            &[("n0", "a"), ("n1", "the ring goes east")],
+            &[("n0", "n1")],
+        );
+
+        let diff = diff(&old, &new, Matching::Labels).unwrap();
+        assert_eq!(
+            diff.to_string(),
+            "~ n1: relabelled 'the ring goes south' to 'the ring goes east'\n- n1: 'gone'\n"
+        );
+    }
+
+    #[test]
+    fn draws_both_graphs_in_one_diagram() {
+        let old = test_graph(&[("n0", "a"), ("n1", "b")], &[("n0", "n1")]);
+        let new = test_graph(&[("n0", "a"), ("n1", "c")], &[]);
+
+        let diff = diff(&old, &new, Matching::Labels).unwrap();
+        let dot = diff.to_dot();
+        assert!(dot.contains(r#""removed_n1""#));
+        assert!(dot.contains(r#""n0" -> "removed_n1""#));
+    }
+
+    #[test]
+    fn identical_graphs_have_no_differences() {
+        let old = test_graph(&[("n0", "a"), ("n1", "b")], &[("n0", "n1")]);
+        let diff = diff(&old, &old, Matching::Ids).unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "no differences\n");
+    }
+}
@@ -0,0 +1,466 @@
+use anyhow::{anyhow, Context, Result};
+use microdot_core::command::GraphCommand;
+use microdot_core::graph::Graph;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// how many events go by between snapshots of the whole graph.
+pub const SNAPSHOT_EVERY: usize = 100;
+
+/// One change to the graph, as a line of the event log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    /// seconds since the unix epoch.
+    pub at: u64,
+    #[serde(flatten)]
+    pub change: Change,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Change {
+    /// the graph was rolled back to how it was after an earlier step.
+    Restore {
+        restore: usize,
+    },
+    Command(GraphCommand),
+}
+
+impl Change {
+    pub fn to_help_string(&self) -> String {
+        match self {
+            Change::Restore { restore } => format!("Restore the graph to step {}", restore),
+            Change::Command(command) => command.to_help_string(),
+        }
+    }
+}
+
+/// A point in a graph's history; either a step number, counting the changes made so far, or a
+/// time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Moment {
+    Step(usize),
+    /// seconds since the unix epoch.
+    Time(u64),
+}
+
+impl FromStr for Moment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(step) = s.parse() {
+            return Ok(Moment::Step(step));
+        }
+
+        parse_timestamp(s)
+            .map(Moment::Time)
+            .ok_or_else(|| format!("'{}' is neither a step nor a time like 2024-05-01T14:30", s))
+    }
+}
+
+/// reads a UTC time like `2024-05-01`, `2024-05-01T14:30` or `2024-05-01 14:30:15`.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let rx =
+        Regex::new(r"^(\d{4})-(\d{2})-(\d{2})(?:[T ](\d{2}):(\d{2})(?::(\d{2}))?)?Z?$").unwrap();
+    let captures = rx.captures(s)?;
+    let part = |i: usize| -> Option<u64> {
+        match captures.get(i) {
+            Some(m) => m.as_str().parse().ok(),
+            None => Some(0),
+        }
+    };
+    let (year, month, day) = (part(1)?, part(2)?, part(3)?);
+    let (hour, minute, second) = (part(4)?, part(5)?, part(6)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let days = days_from_civil(year as i64, month as i64, day as i64);
+    let seconds = days * 86400 + (hour * 3600 + minute * 60 + second) as i64;
+    u64::try_from(seconds).ok()
+}
+
+/// shows a time from the event log as `2024-05-01 14:30:15`, in UTC.
+pub fn format_timestamp(at: u64) -> String {
+    let (days, seconds) = ((at / 86400) as i64, at % 86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+// days since 1970-01-01 in the proleptic gregorian calendar, after Howard Hinnant's algorithms.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// An append-only log of every command applied to a graph, kept next to the graph's JSON file as
+/// `<name>.events.jsonl`. The graph is rebuilt by replaying the log, starting from the latest
+/// snapshot in `<name>.events.snapshots/` so that long logs stay quick to load.
+pub struct EventLog {
+    path: PathBuf,
+    snapshot_dir: PathBuf,
+    snapshot_every: usize,
+    len: AtomicUsize,
+}
+
+impl EventLog {
+    pub fn for_file(json_file: &Path) -> Result<Self> {
+        let path = json_file.with_extension("events.jsonl");
+        let snapshot_dir = json_file.with_extension("events.snapshots");
+        let len = match std::fs::read_to_string(&path) {
+            Ok(content) => content.lines().filter(|l| !l.trim().is_empty()).count(),
+            Err(_) => 0,
+        };
+
+        Ok(Self {
+            path,
+            snapshot_dir,
+            snapshot_every: SNAPSHOT_EVERY,
+            len: AtomicUsize::new(len),
+        })
+    }
+
+    pub fn snapshot_every(self, snapshot_every: usize) -> Self {
+        Self {
+            snapshot_every: snapshot_every.max(1),
+            ..self
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    /// the number of events in the log.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// begins a new log from an existing graph, which becomes the snapshot everything else is
+    /// replayed on top of.
+    pub fn start(&self, graph: &Graph) -> Result<()> {
+        self.write_snapshot(graph)?;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("creating event log {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// records a command which has just been applied to `graph`.
+    pub fn append(&self, command: &GraphCommand, graph: &Graph) -> Result<()> {
+        self.record(Change::Command(command.clone()), graph)
+    }
+
+    /// rolls the graph back to how it was after `step`, recording that as a change of its own so
+    /// the steps since then can still be revisited.
+    pub fn restore(&self, step: usize) -> Result<Graph> {
+        let len = self.len();
+        if step > len {
+            return Err(anyhow!("there are only {} steps in the history", len));
+        }
+
+        let graph = self.replay_to(step)?;
+        self.record(Change::Restore { restore: step }, &graph)?;
+        if !self.len().is_multiple_of(self.snapshot_every) {
+            self.write_snapshot(&graph)?;
+        }
+        Ok(graph)
+    }
+
+    /// the step the graph had reached at a given time.
+    pub fn step_at(&self, at: u64) -> Result<usize> {
+        Ok(self.events()?.iter().take_while(|e| e.at <= at).count())
+    }
+
+    pub fn step_of(&self, moment: Moment) -> Result<usize> {
+        match moment {
+            Moment::Step(step) => Ok(step),
+            Moment::Time(at) => self.step_at(at),
+        }
+    }
+
+    fn record(&self, change: Change, graph: &Graph) -> Result<()> {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let event = Event { at, change };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("opening event log {}", self.path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&event)?)
+            .with_context(|| format!("appending to event log {}", self.path.display()))?;
+
+        let len = self.len.fetch_add(1, Ordering::SeqCst) + 1;
+        if len.is_multiple_of(self.snapshot_every) {
+            self.write_snapshot(graph)?;
+        }
+        Ok(())
+    }
+
+    pub fn events(&self) -> Result<Vec<Event>> {
+        let content = match std::fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .with_context(|| format!("reading event {} of {}", i + 1, self.path.display()))
+            })
+            .collect()
+    }
+
+    /// rebuilds the graph from the whole log.
+    pub fn replay(&self) -> Result<Graph> {
+        self.replay_to(usize::MAX)
+    }
+
+    /// rebuilds the graph as it was after the first `step` events.
+    pub fn replay_to(&self, step: usize) -> Result<Graph> {
+        let (start, mut graph) = match self.latest_snapshot_at_or_before(step)? {
+            Some(start) => (start, self.read_snapshot(start)?),
+            None => (0, Graph::new()),
+        };
+
+        for event in self.events()?.into_iter().skip(start).take(step - start) {
+            match event.change {
+                Change::Command(command) => {
+                    graph.apply_command(command);
+                }
+                Change::Restore { restore } => graph = self.replay_to(restore)?,
+            }
+        }
+        Ok(graph)
+    }
+
+    fn snapshot_path(&self, step: usize) -> PathBuf {
+        self.snapshot_dir.join(format!("{:08}.json", step))
+    }
+
+    fn write_snapshot(&self, graph: &Graph) -> Result<()> {
+        std::fs::create_dir_all(&self.snapshot_dir)?;
+        let path = self.snapshot_path(self.len());
+        std::fs::write(&path, serde_json::to_string(graph)?)
+            .with_context(|| format!("writing snapshot {}", path.display()))
+    }
+
+    fn read_snapshot(&self, step: usize) -> Result<Graph> {
+        let path = self.snapshot_path(step);
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading snapshot {}", path.display()))?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn latest_snapshot_at_or_before(&self, step: usize) -> Result<Option<usize>> {
+        if !self.snapshot_dir.exists() {
+            return Ok(None);
+        }
+
+        let mut latest = None;
+        for entry in std::fs::read_dir(&self.snapshot_dir)? {
+            let path = entry?.path();
+            let snapshot_step = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<usize>().ok());
+            if let Some(snapshot_step) = snapshot_step.filter(|s| *s <= step) {
+                latest = latest.max(Some(snapshot_step));
+            }
+        }
+        Ok(latest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::JsonExporter;
+    use microdot_core::{Id, Label};
+
+    fn insert(label: &str) -> GraphCommand {
+        GraphCommand::InsertNode {
+            label: Label::new(label),
+        }
+    }
+
+    fn record(log: &EventLog, graph: &mut Graph, command: GraphCommand) {
+        graph.apply_command(command.clone());
+        log.append(&command, graph).unwrap();
+    }
+
+    fn json(graph: &Graph) -> String {
+        JsonExporter::new().export_json(graph)
+    }
+
+    #[test]
+    fn events_are_json_lines() {
+        let event = Event {
+            at: 1700000000,
+            change: Change::Command(GraphCommand::LinkEdge {
+                from: Id::new("n0"),
+                to: Id::new("n1"),
+            }),
+        };
+        let line = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            line,
+            r#"{"at":1700000000,"command":"link_edge","from":"n0","to":"n1"}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&line).unwrap(), event);
+
+        let restore = r#"{"at":1700000000,"restore":3}"#;
+        assert_eq!(
+            serde_json::from_str::<Event>(restore).unwrap().change,
+            Change::Restore { restore: 3 }
+        );
+    }
+
+    #[test]
+    fn reads_moments() {
+        assert_eq!("12".parse(), Ok(Moment::Step(12)));
+        assert_eq!("1970-01-02".parse(), Ok(Moment::Time(86400)));
+        assert_eq!(
+            "2024-05-01T14:30".parse::<Moment>(),
+            Ok(Moment::Time(1714573800))
+        );
+        assert_eq!(format_timestamp(1714573815), "2024-05-01 14:30:15");
+        assert!("yesterday".parse::<Moment>().is_err());
+        assert!("2024-13-01".parse::<Moment>().is_err());
+    }
+
+    #[test]
+    fn restores_earlier_steps() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = EventLog::for_file(&dir.path().join("graph.json")).unwrap();
+        let mut graph = Graph::new();
+        record(&log, &mut graph, insert("a"));
+        record(&log, &mut graph, insert("b"));
+        let before = json(&graph);
+        record(&log, &mut graph, insert("c"));
+
+        let mut graph = log.restore(2).unwrap();
+        assert_eq!(json(&graph), before);
+        assert_eq!(log.len(), 4);
+        assert_eq!(json(&log.replay().unwrap()), before);
+
+        record(&log, &mut graph, insert("d"));
+        assert_eq!(json(&log.replay().unwrap()), json(&graph));
+        assert_eq!(
+            log.replay_to(3).unwrap().to_string(),
+            "Graph: 3 nodes, 0 edges"
+        );
+        assert!(log.restore(99).is_err());
+
+        let events = log.events().unwrap();
+        assert_eq!(log.step_at(events[0].at - 1).unwrap(), 0);
+        assert_eq!(log.step_at(events[4].at).unwrap(), 5);
+    }
+
+    #[test]
+    fn replays_from_snapshots() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("graph.json");
+        let log = EventLog::for_file(&json_file).unwrap().snapshot_every(2);
+        let mut graph = Graph::new();
+
+        record(&log, &mut graph, insert("a"));
+        record(&log, &mut graph, insert("b"));
+        record(&log, &mut graph, insert("c"));
+        record(
+            &log,
+            &mut graph,
+            GraphCommand::DeleteNode {
+                id: Id::new("n1"),
+                keep_edges: false,
+            },
+        );
+        record(
+            &log,
+            &mut graph,
+            GraphCommand::LinkEdge {
+                from: Id::new("n0"),
+                to: Id::new("n2"),
+            },
+        );
+
+        assert!(dir.path().join("graph.events.jsonl").exists());
+        assert!(log.snapshot_path(4).exists());
+        assert_eq!(log.len(), 5);
+        assert_eq!(json(&log.replay().unwrap()), json(&graph));
+
+        let reopened = EventLog::for_file(&json_file).unwrap();
+        assert_eq!(reopened.len(), 5);
+        assert_eq!(json(&reopened.replay().unwrap()), json(&graph));
+        assert_eq!(
+            reopened.replay_to(3).unwrap().to_string(),
+            "Graph: 3 nodes, 0 edges"
+        );
+    }
+
+    #[test]
+    fn starts_from_an_existing_graph() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = EventLog::for_file(&dir.path().join("graph.json")).unwrap();
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("already here"));
+
+        log.start(&graph).unwrap();
+        assert!(log.exists());
+        assert!(log.is_empty());
+
+        record(&log, &mut graph, insert("added later"));
+        assert_eq!(json(&log.replay().unwrap()), json(&graph));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::diff::DiffStatus;
 use crate::hashmap;
 use crate::util::write_if_different;
 use anyhow::{anyhow, Context, Result};
@@ -5,10 +6,12 @@ use askama::Template;
 use command_macros::cmd;
 use hyphenation::{Language, Load, Standard};
 use microdot_colors::colors::{Color, ColorScheme, Colors};
+use microdot_colors::palettes::Palette;
 use microdot_core::exporter::{Exporter, NodeHighlight};
-use microdot_core::graph::Graph;
+use microdot_core::graph::{Graph, VariableValue};
 use microdot_core::hash::HashTag;
-use microdot_core::labels::NodeInfo;
+use microdot_core::labels::{NodeInfo, Variables};
+use microdot_core::pet::{find_cost_by, CostCalculator, CostGrouping};
 use microdot_core::util::generate_hash;
 use microdot_core::{Id, Label};
 use once_cell::sync::OnceCell;
@@ -31,37 +34,42 @@ pub fn compile(path: &Path) -> Result<()> {
     let input_str = std::fs::read_to_string(path)?;
     let out_file = path.with_extension("svg");
     let html_file = path.with_extension("html");
-    let image_url = out_file.file_name().unwrap().to_string_lossy().to_string();
     let image_title = out_file.file_stem().unwrap().to_string_lossy().to_string();
 
     dot::DotCompiler::compile_dot_str(input_str)
         .and_then(|string| {
-            write_if_different(out_file, string)?;
-            Ok(())
+            write_if_different(out_file, &string)?;
+            Ok(string)
         })
-        .and_then(|_| {
-            let html = ImagePage {
-                image_title,
-                image_url,
-            };
+        .and_then(|svg| {
+            let html = ImagePage::new(image_title, svg);
             let html_content = html.render()?;
             write_if_different(html_file, html_content)?;
             Ok(())
         })
 }
 
+/// renders dot source to svg in memory, without touching the disc.
+pub fn render_svg(dot: &str) -> Result<String> {
+    dot::DotCompiler::compile_dot_str(dot)
+}
+
 #[derive(Template)]
 #[template(path = "image_page.html")]
 pub struct ImagePage {
     image_title: String,
-    image_url: String,
+    /// the diagram itself, so the page can pan and zoom it, and swap in newer versions.
+    svg: String,
 }
 
 impl ImagePage {
-    pub fn new(image_title: impl Into<String>, image_url: impl Into<String>) -> Self {
+    pub fn new(image_title: impl Into<String>, svg: impl AsRef<str>) -> Self {
+        let svg = svg.as_ref();
+        // the xml declaration and doctype can't go inside an html page.
+        let start = svg.find("<svg").unwrap_or(0);
         Self {
             image_title: image_title.into(),
-            image_url: image_url.into(),
+            svg: svg[start..].to_string(),
         }
     }
 }
@@ -72,6 +80,13 @@ pub struct GraphVizExporter {
     edges: Vec<EdgeViewModel>,
     is_left_right: bool,
     display_mode: DisplayMode,
+    heat: Option<HeatScale>,
+    show_legend: bool,
+    tag_counts: BTreeMap<HashTag, usize>,
+    variable_counts: BTreeMap<String, usize>,
+    subtotals: Option<(String, BTreeMap<Option<HashTag>, VariableValue>)>,
+    // each node's variables with formulas worked out, so badges show the computed values.
+    computed: BTreeMap<Id, Variables>,
 }
 
 fn template(template_str: &str, variables: &HashMap<&str, String>) -> String {
@@ -105,6 +120,7 @@ impl Exporter for GraphVizExporter {
             variables,
             subgraph,
         } = NodeInfo::parse(label);
+        let variables = self.computed.remove(id).unwrap_or(variables);
 
         let id = match self.display_mode {
             DisplayMode::Interactive => id.to_string(),
@@ -113,14 +129,23 @@ impl Exporter for GraphVizExporter {
 
         let label_text = fill(&label_text, wrapping_options);
 
-        let bgcolor = match highlight {
-            NodeHighlight::Normal => Colors::white(),
-            NodeHighlight::SearchResult => Color::from_rgb(208, 204, 204),
-            NodeHighlight::CurrentNode => Colors::white(),
+        let heat_color = self.heat.as_ref().map(|heat| {
+            variables
+                .get(&heat.variable_name)
+                .and_then(|var| heat.color_for(&var.value))
+                .unwrap_or_else(Colors::white)
+        });
+
+        let bgcolor = match (highlight, heat_color) {
+            (NodeHighlight::SearchResult, _) => Color::from_rgb(208, 204, 204),
+            (_, Some(heat_color)) => heat_color,
+            (NodeHighlight::Normal, None) => Colors::white(),
+            (NodeHighlight::CurrentNode, None) => Colors::white(),
         };
 
         let mut hash_tags: Vec<_> = vec![];
         for tag in &tags {
+            *self.tag_counts.entry(tag.clone()).or_default() += 1;
             let label = tag.to_string();
             let bgcolor = tag_adjust(ColorScheme::series(tag.hash()).get_fill_color());
             let model = HashTagViewModel { label, bgcolor };
@@ -128,6 +153,7 @@ impl Exporter for GraphVizExporter {
         }
 
         for var in variables.iter() {
+            *self.variable_counts.entry(var.name.clone()).or_default() += 1;
             let label = format!("{}", var);
             let bgcolor =
                 tag_adjust(ColorScheme::series(generate_hash(&var.name)).get_fill_color());
@@ -211,6 +237,73 @@ impl Template for EdgeViewModel {
     const MIME_TYPE: &'static str = "";
 }
 
+/// Maps the values of one variable onto the heat gradient.
+struct HeatScale {
+    variable_name: String,
+    min: VariableValue,
+    max: VariableValue,
+    palette: Palette,
+}
+
+impl HeatScale {
+    const LEGEND_SWATCHES: usize = 6;
+
+    /// builds the scale from the graph's heatmap variable; there's no scale when the heatmap is
+    /// off or no node has a numeric value for the variable.
+    fn from_graph(graph: &Graph) -> Option<Self> {
+        let variable_name = graph.heatmap()?.to_string();
+        let values: Vec<VariableValue> = graph
+            .node_weights(CostCalculator::new(variable_name.clone()))
+            .into_values()
+            .flatten()
+            .filter(|value| value.as_f64().is_some())
+            .collect();
+
+        let min = values.iter().min_by(|a, b| Self::compare(a, b))?.clone();
+        let max = values.iter().max_by(|a, b| Self::compare(a, b))?.clone();
+
+        Some(Self {
+            variable_name,
+            min,
+            max,
+            palette: ColorScheme::heat(),
+        })
+    }
+
+    fn compare(a: &VariableValue, b: &VariableValue) -> std::cmp::Ordering {
+        let a = a.as_f64().unwrap_or_default();
+        let b = b.as_f64().unwrap_or_default();
+        a.total_cmp(&b)
+    }
+
+    fn color_for(&self, value: &VariableValue) -> Option<Color> {
+        let value = value.as_f64()?;
+        let min = self.min.as_f64().unwrap_or_default();
+        let max = self.max.as_f64().unwrap_or_default();
+        let position = if max > min {
+            (value - min) / (max - min)
+        } else {
+            1.0
+        };
+        Some(self.palette.get_gradient_color(position))
+    }
+
+    fn legend(&self) -> HeatLegendViewModel {
+        let last = (Self::LEGEND_SWATCHES - 1) as f64;
+        let swatches = (0..Self::LEGEND_SWATCHES)
+            .map(|i| self.palette.get_gradient_color(i as f64 / last))
+            .collect();
+
+        HeatLegendViewModel {
+            title: format!("${}", self.variable_name),
+            min: escape_label(&self.min.to_string()),
+            max: escape_label(&self.max.to_string()),
+            half_span: Self::LEGEND_SWATCHES / 2,
+            swatches,
+        }
+    }
+}
+
 impl GraphVizExporter {
     pub fn new(display_mode: DisplayMode) -> Self {
         Self {
@@ -219,6 +312,63 @@ impl GraphVizExporter {
             edges: Default::default(),
             subgraphs: Default::default(),
             display_mode,
+            heat: None,
+            show_legend: false,
+            tag_counts: Default::default(),
+            variable_counts: Default::default(),
+            subtotals: None,
+            computed: Default::default(),
+        }
+    }
+
+    fn subgraph_label(&self, subgraph_id: &HashTag) -> String {
+        let subtotal = self.subtotals.as_ref().and_then(|(variable_name, totals)| {
+            let total = totals.get(&Some(subgraph_id.clone()))?;
+            Some(format!("${}: {}", variable_name, total))
+        });
+
+        match subtotal {
+            Some(subtotal) => format!("{}\n{}", subgraph_id, subtotal),
+            None => subgraph_id.to_string(),
+        }
+    }
+
+    /// a key to the badge and subgraph colours, with the number of nodes using each.
+    fn legend(&self) -> LegendViewModel {
+        let tags = self
+            .tag_counts
+            .iter()
+            .map(|(tag, count)| LegendEntryViewModel {
+                label: escape_label(&tag.to_string()),
+                bgcolor: tag_adjust(ColorScheme::series(tag.hash()).get_fill_color()),
+                count: *count,
+            })
+            .collect();
+
+        let variables = self
+            .variable_counts
+            .iter()
+            .map(|(name, count)| LegendEntryViewModel {
+                label: escape_label(&format!("${}", name)),
+                bgcolor: tag_adjust(ColorScheme::series(generate_hash(name)).get_fill_color()),
+                count: *count,
+            })
+            .collect();
+
+        let subgraphs = self
+            .subgraphs
+            .iter()
+            .map(|(subgraph_id, nodes)| LegendEntryViewModel {
+                label: escape_label(&subgraph_id.to_string()),
+                bgcolor: subgraph_adjust(ColorScheme::series(subgraph_id.hash()).get_fill_color()),
+                count: nodes.len(),
+            })
+            .collect();
+
+        LegendViewModel {
+            tags,
+            variables,
+            subgraphs,
         }
     }
 
@@ -237,7 +387,10 @@ impl GraphVizExporter {
                 "  subgraph cluster_{} {{\n",
                 subgraph_id.to_string().replace('#', "")
             ));
-            built.push_str(&format!("  label=\"{}\"", subgraph_id));
+            built.push_str(&format!(
+                "  label={}",
+                to_dot_label_string(&self.subgraph_label(subgraph_id))
+            ));
             built.push('\n');
             built.push_str(&format!("  bgcolor=\"{}\"", bgcolor));
             built.push('\n');
@@ -251,6 +404,18 @@ impl GraphVizExporter {
             built.push_str("  }\n");
         }
 
+        if let Some(heat) = &self.heat {
+            let line = heat.legend().render().unwrap();
+            built.push_str(&line);
+            built.push('\n');
+        }
+
+        if self.show_legend {
+            let line = self.legend().render().unwrap();
+            built.push_str(&line);
+            built.push('\n');
+        }
+
         built.push('\n');
 
         for edge in &self.edges {
@@ -263,6 +428,17 @@ impl GraphVizExporter {
     }
 
     pub fn export_dot(&mut self, graph: &Graph) -> String {
+        self.heat = HeatScale::from_graph(graph);
+        self.show_legend = graph.show_legend();
+        self.subtotals = graph.subtotals().map(|variable_name| {
+            let totals = find_cost_by(
+                graph,
+                CostCalculator::new(variable_name),
+                CostGrouping::Subgraph,
+            );
+            (variable_name.to_string(), totals)
+        });
+        self.computed = graph.evaluate_variables();
         graph.export(self);
 
         let rank_dir = if self.is_left_right { "LR" } else { "TB" };
@@ -281,6 +457,60 @@ impl GraphVizExporter {
     }
 }
 
+/// dot for a diff of two graphs; `nodes` are (id, label, status) and `edges` (from, to, status).
+pub fn export_diff_dot(
+    nodes: &[(String, String, DiffStatus)],
+    edges: &[(String, String, DiffStatus)],
+) -> String {
+    let fill_color = |status: &DiffStatus| match status {
+        DiffStatus::Unchanged => Colors::white(),
+        DiffStatus::Added => Color::from_rgb(183, 228, 167),
+        DiffStatus::Removed => Color::from_rgb(244, 166, 166),
+        DiffStatus::Changed => Color::from_rgb(255, 213, 128),
+    };
+
+    let mut inner_content = String::new();
+    for (id, label, status) in nodes {
+        let style = match status {
+            DiffStatus::Removed => "filled,dashed",
+            _ => "filled",
+        };
+        inner_content.push_str(&format!(
+            "  {} [label={} fillcolor=\"{}\" style=\"{}\"]\n",
+            escape_id(id.as_str()),
+            to_dot_label_string(&NodeInfo::parse(&Label::new(label)).label),
+            fill_color(status),
+            style
+        ));
+    }
+
+    inner_content.push('\n');
+    for (from, to, status) in edges {
+        let attributes = match status {
+            DiffStatus::Added => format!(" [color=\"{}\"]", Color::from_rgb(77, 175, 74)),
+            DiffStatus::Removed => {
+                format!(" [color=\"{}\" style=dashed]", Color::from_rgb(228, 26, 28))
+            }
+            _ => String::new(),
+        };
+        inner_content.push_str(&format!(
+            "  {} -> {}{}\n",
+            escape_id(from.as_str()),
+            escape_id(to.as_str()),
+            attributes
+        ));
+    }
+
+    GraphViewModel {
+        rank_dir: "TB".to_string(),
+        edge_color: ColorScheme::normal().get_stroke_color(),
+        inner_content,
+        width: 2.5,
+    }
+    .render()
+    .unwrap()
+}
+
 fn to_dot_label_string(label: &str) -> String {
     format!("\"{}\"", label.replace('\n', "\\n").replace('"', "\\\""))
 }
@@ -344,6 +574,30 @@ struct HashTagViewModel {
     bgcolor: Color,
 }
 
+#[derive(Template)]
+#[template(path = "heat_legend.html")]
+struct HeatLegendViewModel {
+    title: String,
+    min: String,
+    max: String,
+    half_span: usize,
+    swatches: Vec<Color>,
+}
+
+#[derive(Template)]
+#[template(path = "legend.html")]
+struct LegendViewModel {
+    tags: Vec<LegendEntryViewModel>,
+    variables: Vec<LegendEntryViewModel>,
+    subgraphs: Vec<LegendEntryViewModel>,
+}
+
+struct LegendEntryViewModel {
+    label: String,
+    bgcolor: Color,
+    count: usize,
+}
+
 #[derive(Template)]
 #[template(path = "graph.txt")]
 struct GraphViewModel {
@@ -452,6 +706,88 @@ mod tests {
         println!("{}", label.render().unwrap());
     }
 
+    #[test]
+    fn heatmap_colours_nodes_and_adds_legend() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("cheap $cost=1"));
+        graph.insert_node(Label::new("pricey $cost=11"));
+        graph.insert_node(Label::new("unknown"));
+        graph.set_heatmap(Some("cost".to_string()));
+
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        let dot = exporter.export_dot(&graph);
+
+        let heat = ColorScheme::heat();
+        assert!(dot.contains("heat_legend"));
+        assert!(dot.contains(&format!(r#"fillcolor="{}""#, heat.get_gradient_color(0.0))));
+        assert!(dot.contains(&format!(r#"fillcolor="{}""#, heat.get_gradient_color(1.0))));
+        assert!(dot.contains(&format!(r#"fillcolor="{}""#, Colors::white())));
+
+        graph.set_heatmap(None);
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        assert!(!exporter.export_dot(&graph).contains("heat_legend"));
+    }
+
+    #[test]
+    fn legend_lists_tags_variables_and_subgraphs() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("first #tag $cost=1 #SG_ONE"));
+        graph.insert_node(Label::new("second #tag #other $cost=2 #SG_ONE"));
+        graph.insert_node(Label::new("third"));
+
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        assert!(!exporter.export_dot(&graph).contains("cluster_legend"));
+
+        graph.toggle_legend();
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        let dot = exporter.export_dot(&graph);
+
+        let legend = exporter.legend();
+        let counts = |entries: &[LegendEntryViewModel]| {
+            entries
+                .iter()
+                .map(|e| (e.label.clone(), e.count))
+                .collect::<Vec<_>>()
+        };
+        assert!(dot.contains("cluster_legend"));
+        assert_eq!(
+            counts(&legend.tags),
+            vec![("#other".to_string(), 1), ("#tag".to_string(), 2)]
+        );
+        assert_eq!(counts(&legend.variables), vec![("$cost".to_string(), 2)]);
+        assert_eq!(counts(&legend.subgraphs), vec![("ONE".to_string(), 2)]);
+    }
+
+    #[test]
+    fn subgraph_labels_can_show_subtotals() {
+        let mut graph = Graph::new();
+        graph.insert_node(Label::new("a $t=1h #SG_ONE"));
+        graph.insert_node(Label::new("b $t=2h #SG_ONE"));
+        graph.insert_node(Label::new("c #SG_TWO"));
+
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        assert!(exporter.export_dot(&graph).contains(r#"label="ONE""#));
+
+        graph.set_subtotals(Some("t".to_string()));
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        let dot = exporter.export_dot(&graph);
+        assert!(dot.contains(r#"label="ONE\n$t: 3 hours""#));
+        assert!(dot.contains(r#"label="TWO""#));
+    }
+
+    #[test]
+    fn badges_show_computed_variables() {
+        let mut graph = Graph::new();
+        let (parent, _) = graph.insert_node(Label::new("parent $cost=sum(children.$cost)"));
+        let (child, _) = graph.insert_node(Label::new("child $t=2h $people=3 $cost=$t*$people"));
+        graph.link_edge(&parent, &child);
+
+        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+        let dot = exporter.export_dot(&graph);
+        assert!(dot.contains("cost=6 hours"), "{}", dot);
+        assert!(!dot.contains("sum(children"), "{}", dot);
+    }
+
     #[test]
     fn converts_to_dot_label_string() {
         assert_eq!(r#""abc""#, to_dot_label_string("abc"));
@@ -1,10 +1,13 @@
-use anyhow::Result;
+use crate::storage::Store;
+use anyhow::{anyhow, Context, Result};
+use jsonschema::JSONSchema;
 use microdot_core::exporter::{Exporter, NodeHighlight};
-use microdot_core::graph::Graph;
+use microdot_core::graph::{Graph, ViewState};
 use microdot_core::{Id, Label};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -13,12 +16,11 @@ use std::path::Path;
 pub struct JsonExporter {
     nodes: Vec<Value>,
     edges: Vec<Value>,
-    is_left_right: bool,
 }
 
 impl Exporter for JsonExporter {
-    fn set_direction(&mut self, is_left_right: bool) {
-        self.is_left_right = is_left_right;
+    fn set_direction(&mut self, _is_left_right: bool) {
+        // the direction is saved with the rest of the view state.
     }
 
     fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight) {
@@ -49,52 +51,196 @@ impl JsonExporter {
         graph.export(self);
 
         let value = json! {{
+        "format_version": FORMAT_VERSION,
         "nodes": self.nodes,
         "edges": self.edges,
-        "is_left_right": self.is_left_right
+        "view": graph.view_state()
         }};
         serde_json::to_string_pretty(&value).expect("could not serialise json")
     }
 }
 
+/// The version of the JSON written by `JsonExporter`. Bump it, and add a step to `MIGRATIONS`,
+/// whenever the format changes.
+pub const FORMAT_VERSION: u64 = 2;
+
+/// `MIGRATIONS[n]` upgrades a version `n` document to version `n + 1`.
+const MIGRATIONS: &[fn(&mut Value) -> Result<()>] = &[migrate_v0_to_v1, migrate_v1_to_v2];
+
+/// The JSON schema for the current format.
+pub const SCHEMA: &str = include_str!("graph.schema.json");
+
+/// the format version of a document; documents from before versioning are version 0.
+fn format_version(value: &Value) -> Result<u64> {
+    match value.get("format_version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("format_version should be a whole number, not {}", version)),
+    }
+}
+
+/// upgrades a document, one version at a time, to the current format.
+fn migrate(mut value: Value) -> Result<Value> {
+    let version = format_version(&value)?;
+    if version > FORMAT_VERSION {
+        return Err(anyhow!(
+            "this file is format version {}, but this microdot only understands up to version {}; please upgrade microdot",
+            version,
+            FORMAT_VERSION
+        ));
+    }
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(&mut value).with_context(|| format!("upgrading from format version {}", from))?;
+        value["format_version"] = json!(from + 1);
+    }
+
+    Ok(value)
+}
+
+/// version 0 files were sometimes written by hand, and could leave out the edges or direction.
+fn migrate_v0_to_v1(value: &mut Value) -> Result<()> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("a graph should be a json object"))?;
+    object.entry("edges").or_insert_with(|| json!([]));
+    object.entry("is_left_right").or_insert(json!(false));
+    Ok(())
+}
+
+/// version 2 moved the direction into the view state.
+fn migrate_v1_to_v2(value: &mut Value) -> Result<()> {
+    let object = value
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("a graph should be a json object"))?;
+    let is_left_right = object.remove("is_left_right").unwrap_or(json!(false));
+    object.insert(
+        "view".to_string(),
+        json!({ "is_left_right": is_left_right }),
+    );
+    Ok(())
+}
+
+/// A problem found when validating a document against the schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaError {
+    /// a JSON pointer to the offending value, e.g. `/nodes/3/label`.
+    pub location: String,
+    pub message: String,
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let location = if self.location.is_empty() {
+            "/"
+        } else {
+            &self.location
+        };
+        write!(f, "{}: {}", location, self.message)
+    }
+}
+
+/// Checks a document against the schema, after upgrading it to the current version. Documents
+/// which aren't JSON at all are an `Err`, with the line and column of the problem.
+pub fn validate(content: &str) -> Result<Vec<SchemaError>> {
+    let value: Value = serde_json::from_str(content).context("not valid json")?;
+    let value = migrate(value)?;
+
+    let schema: Value = serde_json::from_str(SCHEMA).expect("the schema is valid json");
+    let schema = JSONSchema::compile(&schema).expect("the schema is a valid json schema");
+
+    let errors = match schema.validate(&value) {
+        Ok(()) => vec![],
+        Err(errors) => errors
+            .map(|e| SchemaError {
+                location: e.instance_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect(),
+    };
+    Ok(errors)
+}
+
 pub struct JsonImporter {
     content: String,
+    restore_view_state: bool,
 }
 
-#[derive(Serialize, Deserialize)]
-struct JsonNode {
-    id: Id,
-    label: Label,
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct JsonNode {
+    pub(crate) id: Id,
+    pub(crate) label: Label,
 }
 
-#[derive(Serialize, Deserialize)]
-struct JsonEdge {
-    from: Id,
-    to: Id,
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct JsonEdge {
+    pub(crate) from: Id,
+    pub(crate) to: Id,
 }
 
+/// a graph document as it's written to disc, ids and all.
 #[derive(Serialize, Deserialize, Default)]
-struct JsonGraph {
-    nodes: Vec<JsonNode>,
-    edges: Vec<JsonEdge>,
-    is_left_right: bool,
+pub(crate) struct JsonGraph {
+    pub(crate) format_version: u64,
+    pub(crate) nodes: Vec<JsonNode>,
+    pub(crate) edges: Vec<JsonEdge>,
+    #[serde(default)]
+    pub(crate) view: ViewState,
+}
+
+impl JsonGraph {
+    /// reads a document of any version, upgrading it to the current one.
+    pub(crate) fn parse(content: &str) -> Result<Self> {
+        let value = migrate(serde_json::from_str(content)?)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// writes the document the same way `JsonExporter` does.
+    pub(crate) fn to_json(&self) -> String {
+        let value = serde_json::to_value(self).expect("could not serialise json");
+        serde_json::to_string_pretty(&value).expect("could not serialise json")
+    }
+}
+
+/// a graph document with `nodes`, as (id, label), and `edges`, as (from, to); for tests which
+/// need particular ids.
+#[cfg(test)]
+pub(crate) fn test_graph(nodes: &[(&str, &str)], edges: &[(&str, &str)]) -> String {
+    let nodes: Vec<_> = nodes
+        .iter()
+        .map(|(id, label)| json!({"id": id, "label": label}))
+        .collect();
+    let edges: Vec<_> = edges
+        .iter()
+        .map(|(from, to)| json!({"from": from, "to": to}))
+        .collect();
+    json!({"format_version": FORMAT_VERSION, "nodes": nodes, "edges": edges}).to_string()
 }
 
 impl JsonImporter {
     pub fn new<S: Into<String>>(content: S) -> Self {
         JsonImporter {
             content: content.into(),
+            restore_view_state: true,
+        }
+    }
+
+    /// loads just the graph, starting with a fresh view rather than the saved selection, search
+    /// and so on.
+    pub fn ignoring_view_state(self) -> Self {
+        JsonImporter {
+            restore_view_state: false,
+            ..self
         }
     }
 
     pub fn import(&self) -> Result<Graph, anyhow::Error> {
-        let value: JsonGraph = serde_json::from_str(&self.content)?;
+        let value = JsonGraph::parse(&self.content)?;
 
         let mut translate = HashMap::new();
         let mut graph = Graph::new();
 
-        graph.set_direction(value.is_left_right);
-
         for node in &value.nodes {
             let (new_id, _) = graph.insert_node(node.label.clone());
             translate.insert(node.id.clone(), new_id);
@@ -108,6 +254,20 @@ impl JsonImporter {
             }
         }
 
+        let view = if self.restore_view_state {
+            let current_node = value
+                .view
+                .current_node
+                .and_then(|id| translate.get(&id).cloned());
+            ViewState {
+                current_node,
+                ..value.view
+            }
+        } else {
+            ViewState::default()
+        };
+        graph.set_view_state(view);
+
         Ok(graph)
     }
 
@@ -122,7 +282,26 @@ impl JsonImporter {
 
         let importer = JsonImporter::new(json_content);
         let graph = importer.import()?;
-        println!("Loaded graph from {}: {}", json_file.display(), graph);
+        eprintln!("Loaded graph from {}: {}", json_file.display(), graph);
+        Ok(graph)
+    }
+
+    /// loads a graph from a store, or starts a new one if nothing is saved under the name.
+    pub fn load_from_store(
+        store: &dyn Store,
+        name: &str,
+        restore_view_state: bool,
+    ) -> Result<Graph> {
+        let Some(json_content) = store.read(name)? else {
+            return Ok(Graph::new());
+        };
+
+        let importer = JsonImporter {
+            content: json_content,
+            restore_view_state,
+        };
+        let graph = importer.import()?;
+        eprintln!("Loaded graph {}: {}", name, graph);
         Ok(graph)
     }
 }
@@ -153,6 +332,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn migrates_unversioned_graphs() {
+        let content = include_str!("../../test_data/imports_graph_v0.json").to_string();
+        let graph = JsonImporter::new(content)
+            .import()
+            .expect("could not import");
+        let mut exporter = JsonExporter::new();
+        assert_eq!(
+            include_str!("../../test_data/imports_graph.json").to_string(),
+            exporter.export_json(&graph)
+        );
+
+        let hand_written = r#"{"nodes": [{"id": "n0", "label": "alone"}]}"#;
+        JsonImporter::new(hand_written)
+            .import()
+            .expect("missing edges and direction should be filled in");
+    }
+
+    #[test]
+    fn restores_view_state() {
+        let content = r#"{"format_version": 2, "edges": [],
+            "nodes": [{"id": "n3", "label": "first"}, {"id": "n7", "label": "second #tag"}],
+            "view": {"is_left_right": true, "current_node": "n7", "current_search": "tag",
+                "show_legend": true}}"#;
+
+        let graph = JsonImporter::new(content).import().unwrap();
+        let view = graph.view_state();
+        assert!(view.is_left_right);
+        assert!(view.show_legend);
+        assert_eq!(view.current_node, Some(Id::new("n1")));
+        assert_eq!(view.current_search, Some(Label::new("tag")));
+
+        let graph = JsonImporter::new(content)
+            .ignoring_view_state()
+            .import()
+            .unwrap();
+        assert_eq!(graph.view_state(), ViewState::default());
+    }
+
+    #[test]
+    fn refuses_graphs_from_the_future() {
+        let content = r#"{"format_version": 99, "nodes": [], "edges": [], "is_left_right": false}"#;
+        let Err(error) = JsonImporter::new(content).import() else {
+            panic!("should not import a newer format");
+        };
+        assert!(error.to_string().contains("format version 99"), "{}", error);
+    }
+
+    #[test]
+    fn validates_against_the_schema() {
+        let content = include_str!("../../test_data/imports_graph.json");
+        assert_eq!(validate(content).unwrap(), vec![]);
+
+        let content = r#"{"format_version": 2, "edges": [],
+            "nodes": [{"id": "n0", "label": "ok"}, {"id": "n1", "label": 7}]}"#;
+        let errors = validate(content).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].location, "/nodes/1/label");
+        assert_eq!(
+            errors[0].to_string(),
+            r#"/nodes/1/label: 7 is not of type "string""#
+        );
+
+        assert!(validate("{ nope").is_err());
+    }
+
     #[test]
     fn exports_graph() {
         let mut graph = Graph::new();
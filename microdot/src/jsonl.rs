@@ -0,0 +1,110 @@
+use crate::api::CommandRequest;
+use crate::session::{Created, Session};
+use anyhow::Result;
+use serde::Serialize;
+use std::io::{BufRead, Write};
+
+/// The answer to one request; a line of JSON like
+/// `{"status":"ok","result":"inserted node n3: 'foo'","created":{"nodes":["n3"],"edges":[]},"graph":"Graph: 4 nodes, 2 edges"}`
+/// or `{"status":"error","error":"could not understand 'xyz'"}`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Response {
+    Ok {
+        result: String,
+        /// the nodes and edges the command made, so callers can refer to them afterwards.
+        created: Created,
+        /// a summary of the graph afterwards, like "Graph: 3 nodes, 2 edges".
+        graph: String,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl Response {
+    fn error(error: impl ToString) -> Self {
+        Response::Error {
+            error: error.to_string(),
+        }
+    }
+}
+
+/// drives the graph for editor plugins and scripts, instead of the repl; every line of `input`
+/// is a request, as the api takes them, like `{"cmd": "aft n1 foo"}` or
+/// `{"command": "insert_node", "label": "foo"}`, and each is answered with a line of `output`.
+/// Blank lines are skipped; it stops at the end of the input.
+pub fn run_jsonl(session: &Session, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        serde_json::to_writer(&mut output, &respond(session, &line))?;
+        writeln!(output)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+fn respond(session: &Session, line: &str) -> Response {
+    let request: CommandRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Response::error(format!("could not understand '{}': {}", line, e)),
+    };
+    let command = match request.graph_command() {
+        Ok(command) => command,
+        Err(e) => return Response::error(e),
+    };
+    match session.apply_reporting("jsonl", command) {
+        Ok((result, created)) => Response::Ok {
+            result: result.to_string(),
+            created,
+            graph: session.graph.read().unwrap().to_string(),
+        },
+        Err(e) => Response::error(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::test_session;
+
+    #[test]
+    fn answers_each_request_with_a_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("jsonl.json");
+        let (session, _reload_rx) = test_session(&json_file, None);
+
+        let input = [
+            r#"{"cmd": "i start"}"#,
+            "",
+            r#"{"command": "insert_after_node", "id": "n0", "label": "finish"}"#,
+            r#"{"cmd": "/start"}"#,
+            "not json",
+        ]
+        .join("\n");
+        let mut output = vec![];
+        run_jsonl(&session, input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            r#"{"status":"ok","result":"inserted node n0: 'start'","created":{"nodes":["n0"],"edges":[]},"graph":"Graph: 1 nodes, 0 edges"}"#
+        );
+        assert!(lines[1].contains(r#""created":{"nodes":["n1"],"edges":["e0"]}"#));
+        assert_eq!(
+            lines[2],
+            r#"{"status":"error","error":"'/start' doesn't change the graph; only commands which do can be sent"}"#
+        );
+        assert!(
+            lines[3].starts_with(r#"{"status":"error","error":"could not understand 'not json'"#)
+        );
+        assert!(std::fs::read_to_string(json_file)
+            .unwrap()
+            .contains("finish"));
+    }
+}
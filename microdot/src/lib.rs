@@ -1,33 +1,76 @@
+use crate::events::{format_timestamp, Moment};
+use crate::watch::Notify;
+use microdot_core::aggregate::Aggregation;
 use microdot_core::command::GraphCommand;
+use microdot_core::pet::CostGrouping;
 use microdot_core::{Id, Label, Line};
 use rustyline::history::History;
-use rustyline::{Editor, Helper};
+use rustyline::{Editor, ExternalPrinter, Helper};
 
+pub mod animate;
+pub mod api;
+pub mod auth;
+pub mod diff;
+pub mod events;
 pub mod filters;
 pub mod graphviz;
 pub mod helper;
 pub mod json;
+pub mod jsonl;
+pub mod lock;
+pub mod merge;
 pub mod parser;
+pub mod publish;
 pub mod repl;
-// mod storage;
+pub mod session;
+pub mod storage;
 pub mod svg;
 pub mod util;
+pub mod watch;
 pub mod web;
 
 #[derive(PartialEq, Eq, Debug)]
 pub enum Command {
     GraphCommand(GraphCommand),
     ShowHelp,
-    Search { sub_label: Label },
+    Search {
+        sub_label: Label,
+    },
     PrintDot,
     PrintJson,
-    RenameNodeUnlabelled { id: Id },
+    RenameNodeUnlabelled {
+        id: Id,
+    },
     Save,
-    CriticalPathAnalysis { variable_name: String },
-    CostAnalysis { variable_name: String },
+    CriticalPathAnalysis {
+        variable_name: String,
+    },
+    CostAnalysis {
+        variable_name: String,
+        /// `None` for a plain `cost`, which totals the variable.
+        aggregation: Option<Aggregation>,
+        group_by: Option<CostGrouping>,
+    },
+    Subtotals {
+        variable_name: Option<String>,
+    },
+    RankAnalysis,
+    Heatmap {
+        variable_name: Option<String>,
+    },
+    ToggleLegend,
+    At {
+        moment: Moment,
+    },
+    History,
+    Restore {
+        step: usize,
+    },
     Show,
     Exit,
-    ParseError { line: Line },
+    ParseError {
+        line: Line,
+    },
 }
 
 impl Command {
@@ -49,13 +92,80 @@ impl Command {
                 "do a critical path analysis on the graph using <{}> as the cost",
                 variable_name
             ),
+            Command::RankAnalysis => {
+                "rank nodes by downstream reach, betweenness and pagerank to find root causes"
+                    .into()
+            }
+            Command::Heatmap {
+                variable_name: Some(variable_name),
+            } => format!(
+                "colour each node by its <{}> value, with a legend of the range",
+                variable_name
+            ),
+            Command::Heatmap {
+                variable_name: None,
+            } => "turn off heatmap colouring".into(),
+            Command::ToggleLegend => {
+                "show or hide a legend of hashtags, variables and subgraphs".into()
+            }
+            Command::At {
+                moment: Moment::Step(step),
+            } => format!(
+                "render the graph as it was at step {} into a separate svg",
+                step
+            ),
+            Command::At {
+                moment: Moment::Time(at),
+            } => format!(
+                "render the graph as it was at {} into a separate svg",
+                format_timestamp(*at)
+            ),
+            Command::History => "list recent commands with their step numbers".into(),
+            Command::Restore { step } => format!("roll the graph back to step {}", step),
             Command::Show => "open the diagram in Gapplin".into(),
             Command::Exit => "exit microdot".into(),
             Command::ParseError { line } => format!("could not parse: \"{}\"", line),
-            Command::CostAnalysis { variable_name } => format!(
+            Command::CostAnalysis {
+                variable_name,
+                aggregation: None,
+                group_by: None,
+            } => format!(
                 "sum the cost of all nodes in the grpa using <{}> as the cost",
                 variable_name
             ),
+            Command::CostAnalysis {
+                variable_name,
+                aggregation: None,
+                group_by: Some(CostGrouping::Subgraph),
+            } => format!("subtotal the <{}> cost for each subgraph", variable_name),
+            Command::CostAnalysis {
+                variable_name,
+                aggregation: None,
+                group_by: Some(CostGrouping::HashTag),
+            } => format!("subtotal the <{}> cost for each hashtag", variable_name),
+            Command::CostAnalysis {
+                variable_name,
+                aggregation,
+                group_by,
+            } => format!(
+                "find the {} of <{}> {}",
+                aggregation.unwrap_or(Aggregation::Sum).describe(),
+                variable_name,
+                match group_by {
+                    None => "across all nodes",
+                    Some(CostGrouping::Subgraph) => "for each subgraph",
+                    Some(CostGrouping::HashTag) => "for each hashtag",
+                }
+            ),
+            Command::Subtotals {
+                variable_name: Some(variable_name),
+            } => format!(
+                "show the subtotal of <{}> in each subgraph label",
+                variable_name
+            ),
+            Command::Subtotals {
+                variable_name: None,
+            } => "stop showing subtotals in subgraph labels".into(),
         }
     }
 }
@@ -78,6 +188,10 @@ pub trait Interaction {
     // TODO: bad design. Should be handled outside; really corresponds to 'did the last command
     // dirty the cache'
     fn should_compile(&self) -> bool;
+    /// a way to tell the user something while `read` is waiting for them, if there is one.
+    fn notifier(&mut self) -> Option<Notify> {
+        None
+    }
 }
 
 impl<H, I> Interaction for Editor<H, I>
@@ -100,4 +214,11 @@ where
     fn should_compile(&self) -> bool {
         true
     }
+
+    fn notifier(&mut self) -> Option<Notify> {
+        let mut printer = self.create_external_printer().ok()?;
+        Some(Box::new(move |message| {
+            let _ = printer.print(message);
+        }))
+    }
 }
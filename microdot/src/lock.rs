@@ -0,0 +1,182 @@
+use crate::events::format_timestamp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Who is editing a graph, as recorded in its lock file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    /// seconds since the unix epoch.
+    pub since: u64,
+}
+
+impl LockHolder {
+    fn this_process() -> Self {
+        Self {
+            pid: std::process::id(),
+            host: host_name(),
+            since: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// whether the lock was left behind by a process on this machine which has since stopped.
+    /// Locks from other machines can't be checked, so are never stale.
+    pub fn is_stale(&self) -> bool {
+        let proc = Path::new("/proc");
+        self.host == host_name()
+            && proc.join("self").exists()
+            && !proc.join(self.pid.to_string()).exists()
+    }
+}
+
+impl Display for LockHolder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "process {} on {} since {}",
+            self.pid,
+            self.host,
+            format_timestamp(self.since)
+        )
+    }
+}
+
+fn host_name() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .unwrap_or_else(|| "an unknown host".to_string())
+}
+
+/// An advisory lock on a graph, held for as long as this value lives, so that two sessions don't
+/// save over each other. The lock is a `<file>.lock` file beside the graph.
+#[derive(Debug)]
+pub struct GraphLock {
+    path: PathBuf,
+    holder: LockHolder,
+}
+
+#[derive(Debug)]
+pub enum Locked {
+    Acquired(GraphLock),
+    HeldBy(LockHolder),
+}
+
+impl GraphLock {
+    pub fn path_for(json_file: &Path) -> PathBuf {
+        let mut file_name = json_file.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".lock");
+        json_file.with_file_name(file_name)
+    }
+
+    /// takes the lock on a graph unless someone else has it; `force` takes it anyway.
+    pub fn acquire(json_file: &Path, force: bool) -> Result<Locked> {
+        let path = Self::path_for(json_file);
+        if force {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        let holder = LockHolder::this_process();
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(serde_json::to_string(&holder)?.as_bytes())
+                    .with_context(|| format!("writing lock file {}", path.display()))?;
+                Ok(Locked::Acquired(GraphLock { path, holder }))
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let content = std::fs::read_to_string(&path)?;
+                let holder = serde_json::from_str(&content).with_context(|| {
+                    format!(
+                        "reading lock file {}; use --force to break it",
+                        path.display()
+                    )
+                })?;
+                Ok(Locked::HeldBy(holder))
+            }
+            Err(e) => Err(e).with_context(|| format!("creating lock file {}", path.display())),
+        }
+    }
+}
+
+impl Drop for GraphLock {
+    fn drop(&mut self) {
+        // if someone forced the lock away from us, it's theirs now.
+        let still_ours = std::fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<LockHolder>(&content).ok())
+            .is_some_and(|holder| holder == self.holder);
+        if still_ours {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_one_session_holds_the_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("shared.json");
+
+        let Locked::Acquired(lock) = GraphLock::acquire(&json_file, false).unwrap() else {
+            panic!("the graph should not be locked yet");
+        };
+        assert!(dir.path().join("shared.json.lock").exists());
+
+        let Locked::HeldBy(holder) = GraphLock::acquire(&json_file, false).unwrap() else {
+            panic!("the graph should be locked");
+        };
+        assert_eq!(holder.pid, std::process::id());
+        assert!(!holder.is_stale());
+
+        drop(lock);
+        assert!(!dir.path().join("shared.json.lock").exists());
+        assert!(matches!(
+            GraphLock::acquire(&json_file, false).unwrap(),
+            Locked::Acquired(_)
+        ));
+    }
+
+    #[test]
+    fn force_breaks_a_stale_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("shared.json");
+        let stale = LockHolder {
+            pid: u32::MAX,
+            ..LockHolder::this_process()
+        };
+        std::fs::write(
+            GraphLock::path_for(&json_file),
+            serde_json::to_string(&stale).unwrap(),
+        )
+        .unwrap();
+
+        let Locked::HeldBy(holder) = GraphLock::acquire(&json_file, false).unwrap() else {
+            panic!("the graph should be locked");
+        };
+        if Path::new("/proc/self").exists() {
+            assert!(holder.is_stale());
+        }
+
+        let Locked::Acquired(lock) = GraphLock::acquire(&json_file, true).unwrap() else {
+            panic!("--force should break the lock");
+        };
+        assert_eq!(lock.holder.pid, std::process::id());
+    }
+}
@@ -1,17 +1,30 @@
+use anyhow::{anyhow, Context};
 use clap::{Parser, ValueHint};
+use libmicrodot::animate::animate;
+use libmicrodot::auth::Access;
+use libmicrodot::diff::{diff, Matching};
+use libmicrodot::events::{format_timestamp, EventLog};
+use libmicrodot::graphviz::render_svg;
 use libmicrodot::helper::{GetNodeLabel, MicrodotHelper};
-use libmicrodot::json::JsonImporter;
+use libmicrodot::json::{validate, JsonImporter};
+use libmicrodot::jsonl::run_jsonl;
+use libmicrodot::lock::{GraphLock, Locked};
+use libmicrodot::merge::merge_files;
+use libmicrodot::publish::publish;
 use libmicrodot::repl::repl;
-use libmicrodot::web::run_web_server;
+use libmicrodot::session::Session;
+use libmicrodot::storage::{FileStore, ReadOnlyStore, SqliteStore, Store};
+use libmicrodot::web::{run_dir_server, run_web_server, GraphDir, ServedGraph};
 use microdot_core::graph::*;
 use microdot_core::*;
 use rustyline::{Config, Editor};
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc;
 
 /// a REPL and terminal ui for dot and graphviz
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[clap(author, version, about, long_about = None)]
 struct Opts {
     /// Sets the data source file, defaults to ~/.microdot_graph.json
@@ -22,12 +35,130 @@ struct Opts {
     #[clap(long, value_hint = ValueHint::FilePath)]
     history: Option<PathBuf>,
 
-    /// Optional port number for the web server
-    #[clap(long)]
+    /// Optional port number for the web server; `serve` uses 7777 unless told otherwise
+    #[clap(long, global = true)]
     port: Option<u16>,
+
+    /// The address the web server listens on; 0.0.0.0 shares it with everyone on the network
+    #[clap(long, global = true, default_value = "127.0.0.1")]
+    bind: IpAddr,
+
+    /// Only serve requests which bring this token, as ?token=<token> or an
+    /// `Authorization: Bearer <token>` header
+    #[clap(long, global = true)]
+    token: Option<String>,
+
+    /// A second token, which can look at the graph but not change it
+    #[clap(long, global = true)]
+    read_token: Option<String>,
+
+    /// Keep graphs in this SQLite database, with their full history, instead of in JSON files.
+    /// The graph is named after the --file; rendered diagrams are still written next to it
+    #[clap(long, value_hint = ValueHint::FilePath)]
+    db: Option<PathBuf>,
+
+    /// Record every change in an append-only <file>.events.jsonl log, and rebuild the graph by
+    /// replaying it
+    #[clap(long)]
+    events: bool,
+
+    /// How many earlier versions of the graph to keep in .microdot-backups/ beside it
+    #[clap(long, default_value_t = 10)]
+    backups: usize,
+
+    /// Open the graph without restoring the saved selection, search, heatmap and other view
+    /// settings
+    #[clap(long)]
+    ignore_view_state: bool,
+
+    /// Instead of the REPL, read one JSON request per line from stdin, like {"cmd": "i a node"},
+    /// and answer each with a line of JSON on stdout, for editor plugins and scripts
+    #[clap(long)]
+    jsonl: bool,
+
+    /// Break the lock on the graph and edit it anyway, when a session which has stopped left it
+    /// locked
+    #[clap(long)]
+    force: bool,
+
+    #[clap(subcommand)]
+    command: Option<Subcommand>,
+}
+
+#[derive(clap::Subcommand, Debug, Clone)]
+enum Subcommand {
+    /// Check a graph file against the JSON schema, and report where any problems are
+    Validate {
+        #[clap(value_hint = ValueHint::FilePath)]
+        file: PathBuf,
+    },
+    /// Replay a script of commands, like examples/fellowship.txt, and build an html slideshow
+    /// with a diagram after every command
+    Animate {
+        #[clap(value_hint = ValueHint::FilePath)]
+        script: PathBuf,
+
+        /// Where to write the slideshow; defaults to <script>.animation.html
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        out: Option<PathBuf>,
+    },
+    /// List the nodes, edges, variables and tags which differ between two graph files
+    Diff {
+        #[clap(value_hint = ValueHint::FilePath)]
+        old: PathBuf,
+        #[clap(value_hint = ValueHint::FilePath)]
+        new: PathBuf,
+
+        /// Pair up nodes by their labels rather than their ids, for graphs whose ids have been
+        /// renumbered
+        #[clap(long)]
+        match_labels: bool,
+
+        /// Also draw both graphs in one diagram, with added parts in green, removed parts in red
+        /// and changed nodes in amber
+        #[clap(long, value_hint = ValueHint::FilePath)]
+        svg: Option<PathBuf>,
+    },
+    /// Merge two versions of a graph file for git; register it with
+    /// `git config merge.microdot.driver "microdot merge-driver %O %A %B"`
+    MergeDriver {
+        #[clap(value_hint = ValueHint::FilePath)]
+        base: PathBuf,
+        #[clap(value_hint = ValueHint::FilePath)]
+        ours: PathBuf,
+        #[clap(value_hint = ValueHint::FilePath)]
+        theirs: PathBuf,
+    },
+    /// Serve every graph in a directory in the browser, without a REPL; each graph is opened
+    /// the first time someone looks at it
+    Serve {
+        #[clap(long, value_hint = ValueHint::DirPath)]
+        dir: PathBuf,
+    },
+    /// Write a static website for some graphs, with each graph's diagram, a searchable list of
+    /// its nodes, and a page for every node
+    Publish {
+        #[clap(value_hint = ValueHint::DirPath)]
+        out_dir: PathBuf,
+
+        #[clap(required = true, value_hint = ValueHint::FilePath)]
+        graphs: Vec<PathBuf>,
+    },
+    /// List the saved versions of the --file graph that can be restored
+    Backups,
+    /// Put an earlier version of the --file graph back, as numbered by `backups`
+    RestoreBackup { number: usize },
 }
 
 impl Opts {
+    fn web_address(&self, port: u16) -> SocketAddr {
+        SocketAddr::new(self.bind, port)
+    }
+
+    fn access(&self) -> Access {
+        Access::new(self.token.clone(), self.read_token.clone())
+    }
+
     fn history(&self) -> PathBuf {
         self.history
             .clone()
@@ -35,10 +166,113 @@ impl Opts {
     }
 
     fn file(&self) -> PathBuf {
-        self.file
+        let json_file = self
+            .file
             .clone()
-            .unwrap_or_else(|| dirs::home_dir().unwrap().join("microdot_graph.json"))
+            .unwrap_or_else(|| dirs::home_dir().unwrap().join("microdot_graph.json"));
+        if !json_file.exists() && json_file.extension().is_none() {
+            json_file.with_extension("json")
+        } else {
+            json_file
+        }
     }
+
+    /// where the graph is kept, and its name there.
+    fn store(&self, json_file: &Path) -> Result<(Box<dyn Store>, String), anyhow::Error> {
+        match &self.db {
+            Some(db) => {
+                let name = json_file
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                Ok((Box::new(SqliteStore::open(db)?), name))
+            }
+            None => {
+                let (store, name) = FileStore::for_file(json_file);
+                Ok((Box::new(store.keeping_backups(self.backups)), name))
+            }
+        }
+    }
+
+    /// opens the graph in `json_file` to edit it, locking it; if someone else has it locked it's
+    /// opened read-only.
+    fn open(&self, json_file: &Path) -> Result<OpenGraph, anyhow::Error> {
+        let (store, name) = self.store(json_file)?;
+
+        // only one session at a time may change a graph; anyone else can look at it.
+        let (lock, read_only) = match GraphLock::acquire(json_file, self.force)? {
+            Locked::Acquired(lock) => (Some(lock), None),
+            Locked::HeldBy(holder) => {
+                let reason = format!(
+                    "{} is locked by {}, so it's open read-only",
+                    json_file.display(),
+                    holder
+                );
+                eprintln!("{}: it can be looked at, but not changed.", reason);
+                if holder.is_stale() {
+                    eprintln!("That process has stopped; start with --force to break the lock.");
+                } else {
+                    eprintln!("If that session has gone, start with --force to break the lock.");
+                }
+                (None, Some(reason))
+            }
+        };
+        let store: Arc<dyn Store> = match read_only {
+            Some(reason) => Arc::new(ReadOnlyStore::new(store, reason)),
+            None => Arc::from(store),
+        };
+        // diagrams of a read-only graph go beside the owner's, rather than over them.
+        let render_file = if store.read_only().is_some() {
+            json_file.with_extension("read-only.json")
+        } else {
+            json_file.to_path_buf()
+        };
+
+        let events = self
+            .events
+            .then(|| EventLog::for_file(json_file))
+            .transpose()?;
+        let graph = match &events {
+            Some(events) if events.exists() => {
+                let mut graph = events.replay()?;
+                if self.ignore_view_state {
+                    graph.set_view_state(ViewState::default());
+                }
+                eprintln!(
+                    "Replayed {} events from {}: {}",
+                    events.len(),
+                    events.path().display(),
+                    graph
+                );
+                graph
+            }
+            _ => JsonImporter::load_from_store(store.as_ref(), &name, !self.ignore_view_state)?,
+        };
+        let events = events.filter(|_| store.read_only().is_none()).map(Arc::new);
+        if let Some(events) = events.as_ref().filter(|events| !events.exists()) {
+            events.start(&graph)?;
+        }
+        Ok(OpenGraph {
+            graph,
+            store,
+            name,
+            render_file,
+            events,
+            lock,
+        })
+    }
+}
+
+/// A graph opened by `Opts::open`.
+struct OpenGraph {
+    graph: Graph,
+    store: Arc<dyn Store>,
+    name: String,
+    /// the graph's file, or where a read-only graph's diagrams go instead.
+    render_file: PathBuf,
+    events: Option<Arc<EventLog>>,
+    /// held until the graph is closed; `None` when it's read-only.
+    lock: Option<GraphLock>,
 }
 
 struct GraphGetNodeLabel {
@@ -56,28 +290,115 @@ impl GetNodeLabel for GraphGetNodeLabel {
 async fn main() -> Result<(), anyhow::Error> {
     eprintln!("Microdot: a REPL and terminal ui for dot and graphviz.");
     let opts = Opts::parse();
+
+    match &opts.command {
+        Some(Subcommand::Validate { file }) => return validate_file(file),
+        Some(Subcommand::Diff {
+            old,
+            new,
+            match_labels,
+            svg,
+        }) => return diff_files(old, new, *match_labels, svg.as_deref()),
+        Some(Subcommand::MergeDriver { base, ours, theirs }) => {
+            return merge_driver(base, ours, theirs)
+        }
+        Some(Subcommand::Animate { script, out }) => {
+            let out = animate(script, out.clone())?;
+            println!("wrote {}", out.display());
+            return Ok(());
+        }
+        Some(Subcommand::Serve { dir }) => return serve_dir(&opts, dir).await,
+        Some(Subcommand::Publish { out_dir, graphs }) => {
+            let pages = publish(out_dir, graphs)?;
+            println!(
+                "wrote {} pages for {} graphs; open {}",
+                pages.len(),
+                graphs.len(),
+                out_dir.join("index.html").display()
+            );
+            return Ok(());
+        }
+        _ => {}
+    }
+
     let history = opts.history();
     let json_file = opts.file();
-    let json_file = if !json_file.exists() && json_file.extension().is_none() {
-        json_file.with_extension("json")
-    } else {
-        json_file.to_path_buf()
-    };
 
-    let graph = load_graph_if_exists(&json_file)?;
+    match &opts.command {
+        Some(Subcommand::Backups) => {
+            let (store, name) = opts.store(&json_file)?;
+            return list_backups(store.as_ref(), &name);
+        }
+        Some(Subcommand::RestoreBackup { number }) => {
+            if opts.events {
+                return Err(anyhow!(
+                    "with --events the graph is rebuilt from its event log, which would undo the \
+                     restore; use `restore <step>` in the repl instead"
+                ));
+            }
+            // a session with the graph open would save over the restored version.
+            let _lock = match GraphLock::acquire(&json_file, opts.force)? {
+                Locked::Acquired(lock) => lock,
+                Locked::HeldBy(holder) => {
+                    return Err(anyhow!(
+                        "{} is open in {}; close it before restoring, or use --force if that \
+                         session has gone",
+                        json_file.display(),
+                        holder
+                    ))
+                }
+            };
+            let (store, name) = opts.store(&json_file)?;
+            return restore_backup(store.as_ref(), &name, *number);
+        }
+        _ => {}
+    }
+
+    let OpenGraph {
+        graph,
+        store,
+        name,
+        render_file,
+        events,
+        lock: _lock,
+    } = opts.open(&json_file)?;
     let graph = Arc::new(RwLock::new(graph));
+
+    // Create reload channel
+    let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+    let session = Session::new(graph.clone(), render_file.clone(), store, name, reload_tx)
+        .with_events(events);
+
+    if let Some(port) = opts.port {
+        let svg_path = render_file.with_extension("svg");
+        let html_path = render_file.with_extension("html");
+        let session = session.clone();
+        let addr = opts.web_address(port);
+        let access = opts.access();
+        tokio::spawn(async move {
+            if let Err(e) =
+                run_web_server(addr, access, svg_path, html_path, session, reload_rx).await
+            {
+                eprintln!("Failed to start web server: {}", e);
+            }
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    if opts.jsonl {
+        session.render()?;
+        return run_jsonl(&session, std::io::stdin().lock(), std::io::stdout().lock());
+    }
+
     let gnl = GraphGetNodeLabel {
         graph: graph.clone(),
     };
-
     let h = MicrodotHelper::new(&gnl);
     let config = Config::default();
     let mut rl = Editor::with_config(config)?;
     rl.set_helper(Some(h));
 
-    // Create reload channel
-    let (reload_tx, reload_rx) = mpsc::unbounded_channel();
-
     if rl.load_history(&history).is_err() {
         println!("No previous history at {}.", history.to_string_lossy());
     } else {
@@ -87,31 +408,134 @@ async fn main() -> Result<(), anyhow::Error> {
         );
     }
 
-    if let Some(port) = opts.port {
-        let svg_path = json_file.with_extension("svg");
-        let html_path = json_file.with_extension("html");
-        let json_path = json_file.clone();
-        let reload_rx = reload_rx;
-        tokio::spawn(async move {
-            if let Err(e) = run_web_server(port, svg_path, html_path, json_path, reload_rx).await {
-                eprintln!("Failed to start web server: {}", e);
-            }
-        });
+    repl(&mut rl, &session)?;
 
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    rl.save_history(&history).unwrap();
+
+    Ok(())
+}
+
+/// serves every graph in `dir`, each opened the way the repl would open it.
+async fn serve_dir(opts: &Opts, dir: &Path) -> Result<(), anyhow::Error> {
+    let addr = opts.web_address(opts.port.unwrap_or(7777));
+    let access = opts.access();
+    let opts = opts.clone();
+    let graphs = GraphDir::new(dir.to_path_buf(), move |json_file| {
+        let graph = opts.open(json_file)?;
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        let session = Session::new(
+            Arc::new(RwLock::new(graph.graph)),
+            graph.render_file.clone(),
+            graph.store,
+            graph.name,
+            reload_tx,
+        )
+        .with_events(graph.events);
+        session.render()?;
+        Ok(ServedGraph {
+            session,
+            reload_rx,
+            svg_path: graph.render_file.with_extension("svg"),
+            lock: graph.lock,
+        })
+    });
+    run_dir_server(addr, access, graphs).await
+}
+
+fn validate_file(file: &Path) -> Result<(), anyhow::Error> {
+    let content = std::fs::read_to_string(file)?;
+    let errors = validate(&content).with_context(|| file.display().to_string())?;
+    for error in &errors {
+        println!("{}:{}", file.display(), error);
     }
 
-    repl(&mut rl, &json_file, graph, reload_tx)?;
+    if errors.is_empty() {
+        println!("{} is valid", file.display());
+        Ok(())
+    } else {
+        Err(anyhow!("{} problem(s) in {}", errors.len(), file.display()))
+    }
+}
 
-    rl.save_history(&history).unwrap();
+fn diff_files(
+    old: &Path,
+    new: &Path,
+    match_labels: bool,
+    svg: Option<&Path>,
+) -> Result<(), anyhow::Error> {
+    let read = |path: &Path| {
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))
+    };
+    let matching = if match_labels {
+        Matching::Labels
+    } else {
+        Matching::Ids
+    };
+    let diff = diff(&read(old)?, &read(new)?, matching)?;
+    print!("{}", diff);
 
+    if let Some(svg) = svg {
+        std::fs::write(svg, render_svg(&diff.to_dot())?)
+            .with_context(|| format!("writing {}", svg.display()))?;
+        println!("wrote {}", svg.display());
+    }
     Ok(())
 }
 
-fn load_graph_if_exists(json_file: &Path) -> Result<Graph, anyhow::Error> {
-    if !json_file.exists() {
-        return Ok(Graph::new());
+/// git takes a failure to mean the merge has conflicts, and leaves the file for someone to
+/// check; our side of each conflict is in it.
+fn merge_driver(base: &Path, ours: &Path, theirs: &Path) -> Result<(), anyhow::Error> {
+    let conflicts = merge_files(base, ours, theirs)?;
+    for conflict in &conflicts {
+        eprintln!("conflict: {}", conflict);
     }
 
-    JsonImporter::load(json_file)
+    if conflicts.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} conflict(s) merging the graph", conflicts.len()))
+    }
+}
+
+fn list_backups(store: &dyn Store, name: &str) -> Result<(), anyhow::Error> {
+    let revisions = store.history(name)?;
+    if revisions.is_empty() {
+        println!("no saved versions of {}", name);
+    }
+
+    let latest = revisions.len();
+    for revision in revisions {
+        let saved_at = revision
+            .saved_at
+            .map(format_timestamp)
+            .unwrap_or_else(|| "unknown time".to_string());
+        let summary = JsonImporter::new(revision.content)
+            .import()
+            .map(|graph| graph.to_string())
+            .unwrap_or_else(|e| format!("unreadable: {}", e));
+        let current = if revision.number == latest {
+            " (current)"
+        } else {
+            ""
+        };
+        println!(
+            "{:>4}  {}  {}{}",
+            revision.number, saved_at, summary, current
+        );
+    }
+    Ok(())
+}
+
+fn restore_backup(store: &dyn Store, name: &str, number: usize) -> Result<(), anyhow::Error> {
+    let revision = store
+        .history(name)?
+        .into_iter()
+        .find(|revision| revision.number == number)
+        .ok_or_else(|| anyhow!("{} has no saved version {}; try `backups`", name, number))?;
+
+    // make sure it's a graph before it replaces the current one.
+    JsonImporter::new(revision.content.as_str()).import()?;
+    store.write(name, &revision.content)?;
+    println!("restored version {} of {}", number, name);
+    Ok(())
 }
@@ -0,0 +1,352 @@
+use crate::diff::{match_ids, match_labels};
+use crate::json::{JsonEdge, JsonGraph, JsonNode, FORMAT_VERSION};
+use crate::util::write_atomically;
+use anyhow::{Context, Result};
+use microdot_core::{Id, Label};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// Two sets of changes to the same graph, combined.
+#[derive(Debug)]
+pub struct Merged {
+    pub json: String,
+    /// changes that couldn't both be kept, described for people. Ours is kept for each one.
+    pub conflicts: Vec<String>,
+}
+
+/// a three-way merge of graph documents; `base` is the version both `ours` and `theirs` started
+/// from. Nodes are matched by id, or by label for a side whose ids have been renumbered; nodes
+/// either side added get fresh ids if they collide, and edges are kept unless one side removed
+/// them.
+pub fn merge(base: &str, ours: &str, theirs: &str) -> Result<Merged> {
+    let base = JsonGraph::parse(base)?;
+    let ours = align_ids(&base, JsonGraph::parse(ours)?);
+    let theirs = align_ids(&base, JsonGraph::parse(theirs)?);
+
+    let labels = |graph: &JsonGraph| -> HashMap<Id, Label> {
+        graph
+            .nodes
+            .iter()
+            .map(|n| (n.id.clone(), n.label.clone()))
+            .collect()
+    };
+    let (base_labels, our_labels, their_labels) = (labels(&base), labels(&ours), labels(&theirs));
+
+    let mut conflicts = vec![];
+    let mut nodes = vec![];
+
+    for node in &base.nodes {
+        let (id, label) = (&node.id, &node.label);
+        let kept = match (our_labels.get(id), their_labels.get(id)) {
+            (Some(ours), Some(theirs)) => {
+                if ours == theirs || theirs == label {
+                    Some(ours)
+                } else if ours == label {
+                    Some(theirs)
+                } else {
+                    conflicts.push(format!(
+                        "{} was renamed to '{}' in ours and '{}' in theirs; kept ours",
+                        id, ours, theirs
+                    ));
+                    Some(ours)
+                }
+            }
+            (Some(ours), None) if ours != label => {
+                conflicts.push(format!(
+                    "{} was renamed to '{}' in ours but deleted in theirs; kept it",
+                    id, ours
+                ));
+                Some(ours)
+            }
+            (None, Some(theirs)) if theirs != label => {
+                conflicts.push(format!(
+                    "{} was deleted in ours but renamed to '{}' in theirs; kept it",
+                    id, theirs
+                ));
+                Some(theirs)
+            }
+            _ => None,
+        };
+
+        if let Some(label) = kept {
+            nodes.push(JsonNode {
+                id: id.clone(),
+                label: label.clone(),
+            });
+        }
+    }
+
+    for node in &ours.nodes {
+        if !base_labels.contains_key(&node.id) {
+            nodes.push(node.clone());
+        }
+    }
+
+    // nodes both sides added will often have the same ids, since ids are handed out in order;
+    // theirs make way.
+    let mut taken: HashSet<Id> = base_labels
+        .keys()
+        .chain(our_labels.keys())
+        .chain(their_labels.keys())
+        .cloned()
+        .collect();
+    let mut next_id = 0;
+    let mut translate = HashMap::new();
+    for node in &theirs.nodes {
+        if base_labels.contains_key(&node.id) {
+            continue;
+        }
+
+        let id = if our_labels.contains_key(&node.id) {
+            let id = fresh_id(&mut taken, &mut next_id);
+            translate.insert(node.id.clone(), id.clone());
+            id
+        } else {
+            node.id.clone()
+        };
+        nodes.push(JsonNode {
+            id,
+            label: node.label.clone(),
+        });
+    }
+
+    let translate_edge = |edge: &JsonEdge| JsonEdge {
+        from: translate.get(&edge.from).unwrap_or(&edge.from).clone(),
+        to: translate.get(&edge.to).unwrap_or(&edge.to).clone(),
+    };
+    let base_edges: HashSet<JsonEdge> = base.edges.iter().cloned().collect();
+    let our_edges: HashSet<JsonEdge> = ours.edges.iter().cloned().collect();
+    let their_edges: Vec<JsonEdge> = theirs.edges.iter().map(translate_edge).collect();
+    let their_edge_set: HashSet<JsonEdge> = their_edges.iter().cloned().collect();
+
+    let node_ids: HashSet<&Id> = nodes.iter().map(|n| &n.id).collect();
+    let mut seen = HashSet::new();
+    let edges = ours
+        .edges
+        .iter()
+        .filter(|edge| !base_edges.contains(edge) || their_edge_set.contains(edge))
+        .chain(
+            their_edges
+                .iter()
+                .filter(|edge| !base_edges.contains(edge) && !our_edges.contains(edge)),
+        )
+        .filter(|edge| node_ids.contains(&edge.from) && node_ids.contains(&edge.to))
+        .filter(|edge| seen.insert((*edge).clone()))
+        .cloned()
+        .collect();
+
+    let merged = JsonGraph {
+        format_version: FORMAT_VERSION,
+        nodes,
+        edges,
+        view: ours.view,
+    };
+
+    Ok(Merged {
+        json: merged.to_json(),
+        conflicts,
+    })
+}
+
+/// gives `side`'s nodes the ids they had in `base`. Microdot renumbers nodes when it opens a graph,
+/// so a side saved by another session may not share its ids with the base; if pairing nodes by
+/// label finds more of them unchanged than pairing by id does, they're paired by label. Nodes
+/// the side added keep their ids, unless the base used them for something else.
+fn align_ids(base: &JsonGraph, mut side: JsonGraph) -> JsonGraph {
+    let unchanged = |matches: &HashMap<usize, usize>| {
+        matches
+            .iter()
+            .filter(|(n, o)| side.nodes[**n].label == base.nodes[**o].label)
+            .count()
+    };
+    let by_labels = match_labels(base, &side);
+    if unchanged(&by_labels) <= unchanged(&match_ids(base, &side)) {
+        return side;
+    }
+
+    let base_ids: HashSet<&Id> = base.nodes.iter().map(|n| &n.id).collect();
+    let mut taken: HashSet<Id> = base
+        .nodes
+        .iter()
+        .chain(&side.nodes)
+        .map(|n| n.id.clone())
+        .collect();
+    let mut next_id = 0;
+    let mut translate = HashMap::new();
+    for (n, node) in side.nodes.iter().enumerate() {
+        let id = match by_labels.get(&n) {
+            Some(&o) => base.nodes[o].id.clone(),
+            None if base_ids.contains(&node.id) => fresh_id(&mut taken, &mut next_id),
+            None => continue,
+        };
+        translate.insert(node.id.clone(), id);
+    }
+
+    let translated = |id: &Id| translate.get(id).unwrap_or(id).clone();
+    for node in &mut side.nodes {
+        node.id = translated(&node.id);
+    }
+    for edge in &mut side.edges {
+        *edge = JsonEdge {
+            from: translated(&edge.from),
+            to: translated(&edge.to),
+        };
+    }
+    side.view.current_node = side.view.current_node.as_ref().map(translated);
+    side
+}
+
+/// the first id like `n3`, counting from `next_id`, that isn't `taken`; it's taken afterwards.
+fn fresh_id(taken: &mut HashSet<Id>, next_id: &mut usize) -> Id {
+    let id = loop {
+        let candidate = Id::new(format!("n{}", next_id));
+        *next_id += 1;
+        if !taken.contains(&candidate) {
+            break candidate;
+        }
+    };
+    taken.insert(id.clone());
+    id
+}
+
+/// merges graph files the way git asks a merge driver to: `ours` is replaced with the merge, and
+/// the conflicts, if any, are returned. Git passes `%O %A %B` as `base ours theirs`.
+pub fn merge_files(base: &Path, ours: &Path, theirs: &Path) -> Result<Vec<String>> {
+    let read = |path: &Path| {
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))
+    };
+    let merged = merge(&read(base)?, &read(ours)?, &read(theirs)?)?;
+    write_atomically(ours, merged.json).with_context(|| format!("writing {}", ours.display()))?;
+    Ok(merged.conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{test_graph, JsonExporter, JsonImporter};
+    use microdot_core::command::GraphCommand;
+
+    type Pairs = Vec<(String, String)>;
+
+    /// the nodes, as (id, label), and the edges, as (from, to).
+    fn summary(json: &str) -> (Pairs, Pairs) {
+        let graph = JsonGraph::parse(json).unwrap();
+        let nodes = graph
+            .nodes
+            .into_iter()
+            .map(|n| (n.id.to_string(), n.label.to_string()))
+            .collect();
+        let edges = graph
+            .edges
+            .into_iter()
+            .map(|e| (e.from.to_string(), e.to.to_string()))
+            .collect();
+        (nodes, edges)
+    }
+
+    fn pairs(items: &[(&str, &str)]) -> Pairs {
+        items
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn combines_changes_to_different_nodes() {
+        let base = test_graph(&[("n0", "a"), ("n1", "b")], &[("n0", "n1")]);
+        let ours = test_graph(
+            &[("n0", "A"), ("n1", "b"), ("n2", "ours")],
+            &[("n0", "n1"), ("n1", "n2")],
+        );
+        let theirs = test_graph(
+            &[("n0", "a"), ("n1", "B"), ("n2", "theirs")],
+            &[("n0", "n1"), ("n2", "n0")],
+        );
+
+        let merged = merge(&base, &ours, &theirs).unwrap();
+        assert!(merged.conflicts.is_empty(), "{:?}", merged.conflicts);
+        let (nodes, edges) = summary(&merged.json);
+        assert_eq!(
+            nodes,
+            pairs(&[("n0", "A"), ("n1", "B"), ("n2", "ours"), ("n3", "theirs")])
+        );
+        assert_eq!(edges, pairs(&[("n0", "n1"), ("n1", "n2"), ("n3", "n0")]));
+    }
+
+    #[test]
+    fn keeps_deletions_from_either_side() {
+        let base = test_graph(&[("n0", "a"), ("n1", "b"), ("n2", "c")], &[("n0", "n1")]);
+        let ours = test_graph(&[("n0", "a"), ("n1", "b")], &[]);
+        let theirs = test_graph(&[("n0", "a"), ("n1", "b"), ("n2", "c")], &[("n0", "n1")]);
+
+        let merged = merge(&base, &ours, &theirs).unwrap();
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(
+            summary(&merged.json),
+            (pairs(&[("n0", "a"), ("n1", "b")]), vec![])
+        );
+    }
+
+    #[test]
+    fn reports_conflicting_renames() {
+        let base = test_graph(&[("n0", "a"), ("n1", "b")], &[]);
+        let ours = test_graph(&[("n0", "ours"), ("n1", "b")], &[]);
+        let theirs = test_graph(&[("n0", "theirs")], &[]);
+
+        let merged = merge(&base, &ours, &theirs).unwrap();
+        assert_eq!(
+            merged.conflicts,
+            vec!["n0 was renamed to 'ours' in ours and 'theirs' in theirs; kept ours"]
+        );
+        assert_eq!(summary(&merged.json).0, pairs(&[("n0", "ours")]));
+    }
+
+    #[test]
+    fn matches_nodes_by_label_when_one_side_was_renumbered() {
+        let base = test_graph(
+            &[("n0", "plan"), ("n1", "build"), ("n2", "ship")],
+            &[("n0", "n1"), ("n1", "n2"), ("n0", "n2")],
+        );
+        // ours deleted build, then was opened again, which renumbered ship to n1, and saved.
+        let mut deleted = JsonImporter::new(base.clone()).import().unwrap();
+        deleted.apply_command(GraphCommand::DeleteNode {
+            id: Id::new("n1"),
+            keep_edges: false,
+        });
+        let deleted = JsonExporter::new().export_json(&deleted);
+        let ours = JsonExporter::new().export_json(&JsonImporter::new(deleted).import().unwrap());
+        assert_eq!(summary(&ours).0, pairs(&[("n0", "plan"), ("n1", "ship")]));
+        let theirs = test_graph(
+            &[("n0", "plan"), ("n1", "build"), ("n2", "ship it")],
+            &[("n0", "n1"), ("n1", "n2"), ("n0", "n2")],
+        );
+
+        let merged = merge(&base, &ours, &theirs).unwrap();
+        assert!(merged.conflicts.is_empty(), "{:?}", merged.conflicts);
+        assert_eq!(
+            summary(&merged.json),
+            (
+                pairs(&[("n0", "plan"), ("n2", "ship it")]),
+                pairs(&[("n0", "n2")])
+            )
+        );
+    }
+
+    #[test]
+    fn merges_files_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, content: String| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, content).unwrap();
+            path
+        };
+        let base = write("base", test_graph(&[("n0", "a")], &[]));
+        let ours = write("ours", test_graph(&[("n0", "a"), ("n1", "ours")], &[]));
+        let theirs = write("theirs", test_graph(&[("n0", "A")], &[]));
+
+        let conflicts = merge_files(&base, &ours, &theirs).unwrap();
+        assert!(conflicts.is_empty());
+        let merged = std::fs::read_to_string(&ours).unwrap();
+        assert_eq!(summary(&merged).0, pairs(&[("n0", "A"), ("n1", "ours")]));
+    }
+}
@@ -1,7 +1,10 @@
+use crate::events::Moment;
 use crate::Command;
+use microdot_core::aggregate::Aggregation;
 use microdot_core::command::GraphCommand;
+use microdot_core::pet::CostGrouping;
 use microdot_core::{Id, Label, Line};
-use pom::char_class::{alpha, alphanum, multispace};
+use pom::char_class::{alpha, alphanum, digit, multispace};
 use pom::parser::*;
 
 /// space, tab, etc
@@ -84,6 +87,81 @@ fn cost<'a>() -> Parser<'a, u8, String> {
     (keyword(b"cost")) * label()
 }
 
+fn variable_name<'a>() -> Parser<'a, u8, String> {
+    fn is_hyphen(term: u8) -> bool {
+        term == b'-'
+    }
+
+    let it = (is_a(alpha) + (is_a(alphanum) | is_a(is_underscore) | is_a(is_hyphen)).repeat(0..))
+        .map(|(first, rest)| format!("{}{}", first as char, String::from_utf8(rest).unwrap()));
+
+    spaced(it).name("variable name")
+}
+
+fn cost_grouping<'a>() -> Parser<'a, u8, CostGrouping> {
+    let subgraph = keyword(b"subgraph").map(|_| CostGrouping::Subgraph);
+    let hashtag = (keyword(b"#tag") | keyword(b"tag")).map(|_| CostGrouping::HashTag);
+    keyword(b"by") * (subgraph | hashtag)
+}
+
+fn cost_by<'a>() -> Parser<'a, u8, (String, CostGrouping)> {
+    keyword(b"cost") * variable_name() + cost_grouping()
+}
+
+fn aggregation<'a>() -> Parser<'a, u8, Aggregation> {
+    spaced(is_a(alpha).repeat(1..))
+        .convert(String::from_utf8)
+        .convert(|name| name.parse::<Aggregation>())
+        .name("aggregation")
+}
+
+fn cost_aggregate<'a>() -> Parser<'a, u8, ((String, Aggregation), Option<CostGrouping>)> {
+    keyword(b"cost") * variable_name() + aggregation() + cost_grouping().opt()
+}
+
+fn subtotals<'a>() -> Parser<'a, u8, String> {
+    keyword(b"subtotals") * label()
+}
+
+fn subtotals_off<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"subtotals").discard()
+}
+
+fn rank<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"rank").discard()
+}
+
+fn heat<'a>() -> Parser<'a, u8, String> {
+    keyword(b"heat") * label()
+}
+
+fn heat_off<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"heat").discard()
+}
+
+fn legend<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"legend").discard()
+}
+
+fn at<'a>() -> Parser<'a, u8, Moment> {
+    keyword(b"at") * label().convert(|moment| moment.parse::<Moment>())
+}
+
+fn history<'a>() -> Parser<'a, u8, ()> {
+    keyword(b"history").discard()
+}
+
+fn step<'a>() -> Parser<'a, u8, usize> {
+    spaced(is_a(digit).repeat(1..))
+        .convert(String::from_utf8)
+        .convert(|step| step.parse::<usize>())
+        .name("step")
+}
+
+fn restore<'a>() -> Parser<'a, u8, usize> {
+    keyword(b"restore") * step()
+}
+
 fn exit<'a>() -> Parser<'a, u8, ()> {
     keyword(b"exit").discard()
 }
@@ -201,6 +279,16 @@ pub fn parse_line(line: Line) -> Command {
         return GraphCommand::UnlinkEdge { id: Id::new(id) }.into();
     }
 
+    // must come before rename, which would otherwise read this as 'r ank'
+    if let Ok(()) = rank().parse(text) {
+        return Command::RankAnalysis;
+    }
+
+    // must come before rename, which would otherwise read this as 'r estore'
+    if let Ok(step) = restore().parse(text) {
+        return Command::Restore { step };
+    }
+
     if let Ok((id, label)) = rename_node().parse(text) {
         return GraphCommand::RenameNode {
             id: Id::new(id),
@@ -249,10 +337,36 @@ pub fn parse_line(line: Line) -> Command {
         .into();
     }
 
+    if let Ok(moment) = at().parse(text) {
+        return Command::At { moment };
+    }
+
+    if let Ok(()) = legend().parse(text) {
+        return Command::ToggleLegend;
+    }
+
     if let Ok(()) = exit().parse(text) {
         return Command::Exit;
     }
 
+    // must come before help, which would otherwise read this as 'h istory'
+    if let Ok(()) = history().parse(text) {
+        return Command::History;
+    }
+
+    // must come before help, which would otherwise read this as 'h eat'
+    if let Ok(variable_name) = heat().parse(text) {
+        return Command::Heatmap {
+            variable_name: Some(variable_name.trim().to_string()),
+        };
+    }
+
+    if let Ok(()) = heat_off().parse(text) {
+        return Command::Heatmap {
+            variable_name: None,
+        };
+    }
+
     if let Ok(()) = show_help().parse(text) {
         return Command::ShowHelp;
     }
@@ -273,6 +387,19 @@ pub fn parse_line(line: Line) -> Command {
         return Command::Show;
     }
 
+    // must come before search, which would otherwise read this as 's ubtotals'
+    if let Ok(variable_name) = subtotals().parse(text) {
+        return Command::Subtotals {
+            variable_name: Some(variable_name.trim().to_string()),
+        };
+    }
+
+    if let Ok(()) = subtotals_off().parse(text) {
+        return Command::Subtotals {
+            variable_name: None,
+        };
+    }
+
     if let Ok(sub_label) = search().parse(text) {
         return Command::Search {
             sub_label: Label::new(sub_label),
@@ -282,8 +409,28 @@ pub fn parse_line(line: Line) -> Command {
         return Command::CriticalPathAnalysis { variable_name };
     }
 
+    if let Ok(((variable_name, aggregation), group_by)) = cost_aggregate().parse(text) {
+        return Command::CostAnalysis {
+            variable_name,
+            aggregation: Some(aggregation),
+            group_by,
+        };
+    }
+
+    if let Ok((variable_name, grouping)) = cost_by().parse(text) {
+        return Command::CostAnalysis {
+            variable_name,
+            aggregation: None,
+            group_by: Some(grouping),
+        };
+    }
+
     if let Ok(variable_name) = cost().parse(text) {
-        return Command::CostAnalysis { variable_name };
+        return Command::CostAnalysis {
+            variable_name,
+            aggregation: None,
+            group_by: None,
+        };
     }
 
     Command::ParseError { line }
@@ -372,6 +519,38 @@ mod tests {
         assert_consumes_all![save(), b"save", ()];
         assert_consumes_all![crit(), b"crit cost", "cost"];
         assert_consumes_all![cost(), b"cost var", "var"];
+        assert_consumes_all![
+            cost_by(),
+            b"cost t by subgraph",
+            ("t".to_string(), CostGrouping::Subgraph)
+        ];
+        assert_consumes_all![
+            cost_by(),
+            b"cost t by #tag",
+            ("t".to_string(), CostGrouping::HashTag)
+        ];
+        assert_consumes_all![
+            cost_aggregate(),
+            b"cost t median",
+            (("t".to_string(), Aggregation::Median), None)
+        ];
+        assert_consumes_all![
+            cost_aggregate(),
+            b"cost t max by subgraph",
+            (
+                ("t".to_string(), Aggregation::Max),
+                Some(CostGrouping::Subgraph)
+            )
+        ];
+        assert_consumes_all![subtotals(), b"subtotals t", "t"];
+        assert_consumes_all![subtotals_off(), b"subtotals", ()];
+        assert_consumes_all![rank(), b"rank", ()];
+        assert_consumes_all![heat(), b"heat cost", "cost"];
+        assert_consumes_all![heat_off(), b"heat", ()];
+        assert_consumes_all![legend(), b"legend", ()];
+        assert_consumes_all![at(), b"at 12", Moment::Step(12)];
+        assert_consumes_all![history(), b"history", ()];
+        assert_consumes_all![restore(), b"restore 3", 3];
         assert_consumes_all![search(), b"/foo", "foo"];
         assert_consumes_all![search(), b"s foo", "foo"];
         assert_consumes_all![search(), b"search foo", "foo"];
@@ -499,6 +678,85 @@ mod tests {
 
         assert_parse_command!("save", Command::Save);
 
+        assert_parse_command!("rank", Command::RankAnalysis);
+
+        assert_parse_command!(
+            "cost my-cost",
+            Command::CostAnalysis {
+                variable_name: "my-cost".to_string(),
+                aggregation: None,
+                group_by: None
+            }
+        );
+
+        assert_parse_command!(
+            "cost t avg",
+            Command::CostAnalysis {
+                variable_name: "t".to_string(),
+                aggregation: Some(Aggregation::Avg),
+                group_by: None
+            }
+        );
+
+        assert_parse_command!(
+            "cost done all by #tag",
+            Command::CostAnalysis {
+                variable_name: "done".to_string(),
+                aggregation: Some(Aggregation::All),
+                group_by: Some(CostGrouping::HashTag)
+            }
+        );
+
+        assert_parse_command!(
+            "cost my-cost by subgraph",
+            Command::CostAnalysis {
+                variable_name: "my-cost".to_string(),
+                aggregation: None,
+                group_by: Some(CostGrouping::Subgraph)
+            }
+        );
+
+        assert_parse_command!(
+            "cost t by tag",
+            Command::CostAnalysis {
+                variable_name: "t".to_string(),
+                aggregation: None,
+                group_by: Some(CostGrouping::HashTag)
+            }
+        );
+
+        assert_parse_command!(
+            "subtotals t",
+            Command::Subtotals {
+                variable_name: Some("t".to_string())
+            }
+        );
+
+        assert_parse_command!("legend", Command::ToggleLegend);
+
+        assert_parse_command!(
+            "at 2024-05-01T14:30",
+            Command::At {
+                moment: Moment::Time(1714573800)
+            }
+        );
+        assert_parse_command!("history", Command::History);
+        assert_parse_command!("restore 4", Command::Restore { step: 4 });
+
+        assert_parse_command!(
+            "heat cost",
+            Command::Heatmap {
+                variable_name: Some("cost".to_string())
+            }
+        );
+
+        assert_parse_command!(
+            "heat",
+            Command::Heatmap {
+                variable_name: None
+            }
+        );
+
         assert_parse_command!(
             "search foo",
             Command::Search {
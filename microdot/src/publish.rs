@@ -0,0 +1,284 @@
+use crate::api::{node_views, NodeView};
+use crate::graphviz::{render_svg, DisplayMode, GraphVizExporter};
+use crate::json::JsonImporter;
+use anyhow::{anyhow, Context, Result};
+use askama::Template;
+use microdot_core::graph::Graph;
+use microdot_core::Id;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// A graph to publish, named after its file.
+pub struct Published {
+    pub name: String,
+    pub graph: Graph,
+    pub svg: String,
+}
+
+/// A page of the site, and where it goes, relative to the top of the site.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+#[derive(Template)]
+#[template(path = "publish_index.html")]
+struct IndexPage {
+    graphs: Vec<GraphSummary>,
+}
+
+struct GraphSummary {
+    name: String,
+    summary: String,
+}
+
+#[derive(Template)]
+#[template(path = "publish_graph.html")]
+struct GraphPage {
+    name: String,
+    summary: String,
+    svg: String,
+    nodes: Vec<NodeLink>,
+}
+
+#[derive(Template)]
+#[template(path = "publish_node.html")]
+struct NodePage {
+    graph: String,
+    id: Id,
+    text: String,
+    label: String,
+    tags: Vec<String>,
+    subgraph: Option<String>,
+    variables: Vec<(String, String)>,
+    predecessors: Vec<NodeLink>,
+    successors: Vec<NodeLink>,
+}
+
+struct NodeLink {
+    id: Id,
+    text: String,
+    tags: Vec<String>,
+}
+
+impl NodeLink {
+    fn new(node: &NodeView) -> Self {
+        Self {
+            id: node.id.clone(),
+            text: node.text.clone(),
+            tags: node.tags.clone(),
+        }
+    }
+}
+
+/// variables as they'd be written in a label; `5` rather than `5.0`, and times without quotes.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Number(n) => n
+            .as_f64()
+            .map(|n| n.to_string())
+            .unwrap_or_else(|| n.to_string()),
+        other => other.to_string(),
+    }
+}
+
+/// every page of a site for the graphs; an index of them, and for each graph a page with its
+/// diagram and a searchable list of its nodes, its svg, and a page for each node which links to
+/// the nodes before and after it.
+pub fn site(graphs: &[Published]) -> Result<Vec<Page>> {
+    let mut pages = vec![];
+    let mut names = BTreeSet::new();
+    for published in graphs {
+        if !names.insert(published.name.as_str()) {
+            return Err(anyhow!(
+                "there's more than one graph called '{}'",
+                published.name
+            ));
+        }
+        pages.extend(graph_pages(published)?);
+    }
+
+    let index = IndexPage {
+        graphs: graphs
+            .iter()
+            .map(|published| GraphSummary {
+                name: published.name.clone(),
+                summary: published.graph.to_string(),
+            })
+            .collect(),
+    };
+    pages.insert(
+        0,
+        Page {
+            path: PathBuf::from("index.html"),
+            content: index.render()?,
+        },
+    );
+    Ok(pages)
+}
+
+fn graph_pages(published: &Published) -> Result<Vec<Page>> {
+    let dir = PathBuf::from(&published.name);
+    let nodes = node_views(&published.graph);
+    let by_id: BTreeMap<&Id, &NodeView> = nodes.iter().map(|node| (&node.id, node)).collect();
+    let links = |ids: &[Id]| {
+        ids.iter()
+            .filter_map(|id| by_id.get(id))
+            .map(|node| NodeLink::new(node))
+            .collect()
+    };
+
+    let svg = published
+        .svg
+        .find("<svg")
+        .map(|start| &published.svg[start..])
+        .unwrap_or(&published.svg);
+    let mut pages = vec![
+        Page {
+            path: dir.join("index.html"),
+            content: GraphPage {
+                name: published.name.clone(),
+                summary: published.graph.to_string(),
+                svg: svg.to_string(),
+                nodes: nodes.iter().map(NodeLink::new).collect(),
+            }
+            .render()?,
+        },
+        Page {
+            path: dir.join(format!("{}.svg", published.name)),
+            content: published.svg.clone(),
+        },
+    ];
+
+    for node in &nodes {
+        let page = NodePage {
+            graph: published.name.clone(),
+            id: node.id.clone(),
+            text: node.text.clone(),
+            label: node.label.to_string(),
+            tags: node.tags.clone(),
+            subgraph: node.subgraph.clone(),
+            variables: node
+                .variables
+                .iter()
+                .map(|(name, value)| (name.clone(), display_value(value)))
+                .collect(),
+            predecessors: links(&node.predecessors),
+            successors: links(&node.successors),
+        };
+        pages.push(Page {
+            path: dir.join(format!("{}.html", node.id)),
+            content: page.render()?,
+        });
+    }
+    Ok(pages)
+}
+
+/// writes a static site for the graph files into `out_dir`, and returns the pages written.
+pub fn publish(out_dir: &Path, files: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut graphs = vec![];
+    for file in files {
+        let content =
+            std::fs::read_to_string(file).with_context(|| format!("reading {}", file.display()))?;
+        let graph = JsonImporter::new(content)
+            .import()
+            .with_context(|| format!("reading {}", file.display()))?;
+        let dot = GraphVizExporter::new(DisplayMode::Presentation).export_dot(&graph);
+        let svg = render_svg(&dot).with_context(|| format!("drawing {}", file.display()))?;
+        let name = file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        graphs.push(Published { name, graph, svg });
+    }
+
+    let mut written = vec![];
+    for page in site(&graphs)? {
+        let path = out_dir.join(&page.path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("creating {}", parent.display()))?;
+        }
+        std::fs::write(&path, page.content)
+            .with_context(|| format!("writing {}", path.display()))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdot_core::command::GraphCommand;
+    use microdot_core::Label;
+
+    fn published(name: &str, labels: &[&str]) -> Published {
+        let mut graph = Graph::new();
+        for label in labels {
+            graph.apply_command(GraphCommand::InsertNode {
+                label: Label::new(*label),
+            });
+        }
+        graph.apply_command(GraphCommand::LinkEdge {
+            from: Id::new("n0"),
+            to: Id::new("n1"),
+        });
+        Published {
+            name: name.to_string(),
+            graph,
+            svg: "<?xml version=\"1.0\"?>\n<svg><g class=\"node\"><title>n0</title></g></svg>"
+                .to_string(),
+        }
+    }
+
+    fn page<'a>(pages: &'a [Page], path: &str) -> &'a str {
+        &pages
+            .iter()
+            .find(|page| page.path == Path::new(path))
+            .unwrap_or_else(|| panic!("no page {}", path))
+            .content
+    }
+
+    #[test]
+    fn pages_link_each_node_to_its_neighbours() {
+        let pages = site(&[
+            published("problems", &["too slow $cost=5 #perf", "add a cache"]),
+            published("plans", &["start", "finish"]),
+        ])
+        .unwrap();
+
+        let paths: Vec<_> = pages.iter().map(|page| page.path.clone()).collect();
+        assert_eq!(paths.len(), 1 + 2 * 4);
+        assert!(paths.contains(&PathBuf::from("plans/n1.html")));
+
+        let index = page(&pages, "index.html");
+        assert!(index.contains(r#"href="problems/index.html""#));
+        assert!(index.contains("Graph: 2 nodes, 1 edges"));
+
+        let graph = page(&pages, "problems/index.html");
+        assert!(graph.contains("<svg><g class=\"node\">"));
+        assert!(!graph.contains("<?xml"));
+        assert!(graph.contains(r#"href="n1.html""#));
+        assert!(page(&pages, "problems/problems.svg").starts_with("<?xml"));
+
+        let cause = page(&pages, "problems/n0.html");
+        assert!(cause.contains("too slow"));
+        assert!(cause.contains("#perf"));
+        assert!(cause.contains("<td>$cost</td><td>5</td>"));
+        assert!(cause.contains(r#"<a href="n1.html">add a cache</a>"#));
+        let fix = page(&pages, "problems/n1.html");
+        assert!(fix.contains(r#"<a href="n0.html">too slow</a>"#));
+    }
+
+    #[test]
+    fn graphs_need_different_names() {
+        let result = site(&[
+            published("same", &["a", "b"]),
+            published("same", &["c", "d"]),
+        ]);
+        assert!(result.is_err());
+    }
+}
@@ -1,176 +1,597 @@
+use crate::events::{format_timestamp, EventLog, Moment};
 use crate::graphviz::{DisplayMode, GraphVizExporter};
-use crate::json::JsonExporter;
+use crate::json::{JsonExporter, JsonImporter};
+use crate::merge::merge;
 use crate::parser::parse_line;
+use crate::session::{Session, Update};
+use crate::storage::Store;
 use crate::util::write_if_different;
+use crate::watch::Watch;
 use crate::{graphviz, svg, Command, Interaction};
 use anyhow::{anyhow, Result};
+use microdot_core::aggregate::Aggregation;
+use microdot_core::exporter::{Exporter, NodeHighlight};
 use microdot_core::graph::Graph;
-use microdot_core::pet::{find_cost, find_longest_path, CostCalculator};
-use microdot_core::{CommandResult, Line};
+use microdot_core::hash::HashTag;
+use microdot_core::pet::{
+    find_aggregate, find_aggregate_by, find_longest_path, rank_nodes, CostCalculator, CostGrouping,
+};
+use microdot_core::{CommandResult, Id, Label, Line};
 use rustyline::error::ReadlineError;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::UnboundedSender;
 
-pub fn repl<I: Interaction>(
-    interaction: &mut I,
-    json_file: &Path,
-    graph: Arc<RwLock<Graph>>,
-    reload_tx: UnboundedSender<()>,
-) -> Result<()> {
-    loop {
-        let readline = interaction.read(">> ");
+pub fn repl<I: Interaction>(interaction: &mut I, session: &Session) -> Result<()> {
+    // commands typed here are applied through the session like anyone else's; it only renders
+    // svgs if the interaction wants them.
+    let session = &session.clone().compiling(interaction.should_compile());
+    let json_file = session.json_file.as_path();
+    let store = session.store.as_ref();
+    let name = session.name.as_str();
+    let events = session.events.as_deref();
+    let graph = session.graph.clone();
+    let reload_tx = session.reload_tx.clone();
+
+    // a read-only store keeps whatever its owner saved, so their changes are watched for too.
+    // With an event log the JSON is only a copy, so it's never watched.
+    let watch = &Watch::default();
+    let notify = interaction.notifier();
+    let (stop_watching, stop) = std::sync::mpsc::channel();
+
+    std::thread::scope(|scope| {
+        // dropped when the repl finishes, which stops the watch.
+        let _stop_watching = stop_watching;
+        if events.is_none() {
+            let graph = &graph;
+            scope.spawn(move || watch.run(store, name, graph, notify, stop));
+        }
 
         // when we start, make sure the existing pic is up to date.
-        compile_graph(interaction, json_file, &graph, Some(&reload_tx))?;
+        watch.save(|| {
+            compile_graph(
+                interaction,
+                json_file,
+                store,
+                name,
+                &graph,
+                Some(&reload_tx),
+            )?;
+            store.read(name)
+        })?;
 
-        let dirty = match readline {
-            Ok(line) => {
-                interaction.add_history(line.as_str());
+        loop {
+            // anything someone else changed while the last command ran is dealt with before the next
+            // is read, and anything they changed while it was being typed before it's run.
+            check_external_changes(
+                interaction,
+                json_file,
+                store,
+                name,
+                &graph,
+                &reload_tx,
+                watch,
+            )?;
+            let readline = interaction.read(">> ");
+            let renumbered = check_external_changes(
+                interaction,
+                json_file,
+                store,
+                name,
+                &graph,
+                &reload_tx,
+                watch,
+            )?;
 
-                let line = Line::new(line);
+            let dirty = match readline {
+                // a command typed before a reload which changed what the ids refer to could
+                // delete or link the wrong nodes.
+                Ok(line) if renumbered && !line.trim().is_empty() => {
+                    interaction.add_history(line.as_str());
+                    interaction.log(format!(
+                        "(the graph was reloaded and its ids have changed, so '{}' wasn't run; check the ids and try it again)",
+                        line
+                    ));
+                    false
+                }
+                Ok(line) => {
+                    interaction.add_history(line.as_str());
 
-                let command = parse_line(line);
+                    let line = Line::new(line);
 
-                match command {
-                    Command::GraphCommand(graph_command) => {
-                        let mut graph = graph.write().unwrap();
-                        let applied = graph.apply_command(graph_command);
-                        interaction.log(format!("({})", applied));
-                        true
-                    }
-                    Command::ShowHelp => {
-                        interaction.log(include_str!("help.txt"));
-                        false
-                    }
-                    Command::RenameNodeUnlabelled { .. } => {
-                        // no need to act, this is for auto-complete
-                        false
-                    }
-                    Command::Show => {
-                        let svg_file = json_file.with_extension("svg");
-                        let svg_file = std::fs::canonicalize(svg_file)
-                            .expect("could not canconcicalise file path");
-                        let result = svg::open_in_gapplin(&svg_file);
-                        interaction.log(result.to_string());
-                        false
-                    }
-                    Command::PrintDot => {
-                        let graph = graph.read().unwrap();
-                        let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
-                        let out = exporter.export_dot(&graph);
-                        interaction.log(out);
-                        interaction.log("Dot printed");
-                        false
-                    }
-                    Command::PrintJson => {
-                        let graph = graph.read().unwrap();
-                        let mut exporter = JsonExporter::new();
-                        let out = exporter.export_json(&graph);
-                        interaction.log(out);
-                        interaction.log("Json printed");
-                        false
-                    }
-                    Command::Search { sub_label } => {
-                        let mut graph = graph.write().unwrap();
-                        interaction.log(format!("({})", graph.highlight_search_results(sub_label)));
-                        true
-                    }
-                    Command::Save => {
-                        interaction.log(format!("saving to {}", json_file.to_string_lossy()));
-                        true
-                    }
-                    Command::CriticalPathAnalysis { variable_name } => {
-                        let graph = graph.read().unwrap();
-                        interaction.log(format!(
-                            "performing critical path analysis using variable {}",
-                            variable_name
-                        ));
-
-                        let longest_path =
-                            find_longest_path(&graph, CostCalculator::new(variable_name.clone()));
-                        for (i, node) in longest_path.ids.iter().enumerate() {
-                            if let Some(label) = graph.find_node_label(node) {
-                                let val = match graph.find_node_variable_value(node, &variable_name)
-                                {
-                                    Some(val) => format!("{}", val),
-                                    None => "".to_string(),
-                                };
-                                interaction.log(format!("Step {}: {}: {}", i + 1, val, label));
-                            }
+                    let command = parse_line(line);
+
+                    match command {
+                        Command::GraphCommand(graph_command) => {
+                            // saved under the watch, so it isn't mistaken for someone else's save.
+                            watch.save(|| {
+                                match session.apply("repl", graph_command) {
+                                    Ok(applied) => interaction.log(format!("({})", applied)),
+                                    Err(e) if session.read_only().is_some() => {
+                                        interaction.log(format!("({}; that command wasn't run)", e))
+                                    }
+                                    Err(e) => return Err(e),
+                                }
+                                store.read(name)
+                            })?;
+                            false
+                        }
+                        Command::ShowHelp => {
+                            interaction.log(include_str!("help.txt"));
+                            false
+                        }
+                        Command::RenameNodeUnlabelled { .. } => {
+                            // no need to act, this is for auto-complete
+                            false
+                        }
+                        Command::Show => {
+                            let svg_file = json_file.with_extension("svg");
+                            let svg_file = std::fs::canonicalize(svg_file)
+                                .expect("could not canconcicalise file path");
+                            let result = svg::open_in_gapplin(&svg_file);
+                            interaction.log(result.to_string());
+                            false
+                        }
+                        Command::PrintDot => {
+                            let graph = graph.read().unwrap();
+                            let mut exporter = GraphVizExporter::new(DisplayMode::Interactive);
+                            let out = exporter.export_dot(&graph);
+                            interaction.log(out);
+                            interaction.log("Dot printed");
+                            false
                         }
+                        Command::PrintJson => {
+                            let graph = graph.read().unwrap();
+                            let mut exporter = JsonExporter::new();
+                            let out = exporter.export_json(&graph);
+                            interaction.log(out);
+                            interaction.log("Json printed");
+                            false
+                        }
+                        Command::Search { sub_label } => {
+                            let mut graph = graph.write().unwrap();
+                            interaction
+                                .log(format!("({})", graph.highlight_search_results(sub_label)));
+                            true
+                        }
+                        Command::Heatmap { variable_name } => {
+                            let mut graph = graph.write().unwrap();
+                            interaction.log(format!("({})", graph.set_heatmap(variable_name)));
+                            true
+                        }
+                        Command::ToggleLegend => {
+                            let mut graph = graph.write().unwrap();
+                            interaction.log(format!("({})", graph.toggle_legend()));
+                            true
+                        }
+                        Command::Save => {
+                            interaction.log(format!("saving {}", name));
+                            true
+                        }
+                        Command::CriticalPathAnalysis { variable_name } => {
+                            let graph = graph.read().unwrap();
+                            interaction.log(format!(
+                                "performing critical path analysis using variable {}",
+                                variable_name
+                            ));
 
-                        if !longest_path.ids.is_empty() {
-                            interaction.log("====================");
-                            if let Some(cost) = longest_path.cost {
-                                interaction.log(format!("Total cost: {}", cost));
+                            let longest_path = find_longest_path(
+                                &graph,
+                                CostCalculator::new(variable_name.clone()),
+                            );
+                            for (i, node) in longest_path.ids.iter().enumerate() {
+                                if let Some(label) = graph.find_node_label(node) {
+                                    let val = match graph
+                                        .find_node_variable_value(node, &variable_name)
+                                    {
+                                        Some(val) => format!("{}", val),
+                                        None => "".to_string(),
+                                    };
+                                    interaction.log(format!("Step {}: {}: {}", i + 1, val, label));
+                                }
                             }
-                            interaction.log(format!("Total length: {}", longest_path.ids.len()));
-                        }
 
-                        false
-                    }
-                    Command::ParseError { .. } => {
-                        interaction.log("could not understand command; try 'h' for help");
-                        false
-                    }
-                    Command::Exit => return Ok(()),
-                    Command::CostAnalysis { variable_name } => {
-                        let graph = graph.read().unwrap();
-                        interaction.log(format!(
-                            "performing cost analysis using variable {}",
-                            variable_name
-                        ));
-                        let cost = find_cost(&graph, CostCalculator::new(variable_name.clone()));
-                        interaction.log(format!("Total cost: {}", cost));
-                        false
+                            if !longest_path.ids.is_empty() {
+                                interaction.log("====================");
+                                if let Some(cost) = longest_path.cost {
+                                    interaction.log(format!("Total cost: {}", cost));
+                                }
+                                interaction
+                                    .log(format!("Total length: {}", longest_path.ids.len()));
+                            }
+
+                            false
+                        }
+                        Command::ParseError { .. } => {
+                            interaction.log("could not understand command; try 'h' for help");
+                            false
+                        }
+                        Command::Exit => return Ok(()),
+                        Command::CostAnalysis {
+                            variable_name,
+                            aggregation,
+                            group_by,
+                        } => {
+                            let graph = graph.read().unwrap();
+                            // a plain `cost` keeps its old wording, since scripts may read it.
+                            let (intro, overall) = match aggregation {
+                                None => (
+                                    format!(
+                                        "performing cost analysis using variable {}",
+                                        variable_name
+                                    ),
+                                    "Total cost".to_string(),
+                                ),
+                                Some(aggregation) => (
+                                    format!(
+                                        "finding the {} of variable {}",
+                                        aggregation.describe(),
+                                        variable_name
+                                    ),
+                                    format!("Overall {}", aggregation),
+                                ),
+                            };
+                            let aggregation = aggregation.unwrap_or(Aggregation::Sum);
+                            interaction.log(intro);
+                            if let Some(grouping) = group_by {
+                                let groups = find_aggregate_by(
+                                    &graph,
+                                    CostCalculator::new(variable_name.clone()),
+                                    grouping,
+                                    aggregation,
+                                );
+                                for (group, aggregated) in groups {
+                                    interaction.log(format!(
+                                        "{}: {}",
+                                        group_name(group, grouping),
+                                        aggregated
+                                    ));
+                                }
+                                interaction.log("====================");
+                            }
+                            let aggregated = find_aggregate(
+                                &graph,
+                                CostCalculator::new(variable_name.clone()),
+                                aggregation,
+                            );
+                            interaction.log(format!("{}: {}", overall, aggregated));
+                            false
+                        }
+                        Command::Subtotals { variable_name } => {
+                            let mut graph = graph.write().unwrap();
+                            interaction.log(format!("({})", graph.set_subtotals(variable_name)));
+                            true
+                        }
+                        Command::RankAnalysis => {
+                            let graph = graph.read().unwrap();
+                            interaction.log("ranking nodes by downstream reach");
+                            interaction.log(format!(
+                                "{:>4}  {:>5}  {:>11}  {:>8}  Node",
+                                "Rank", "Reach", "Betweenness", "PageRank"
+                            ));
+                            for (i, rank) in rank_nodes(&graph).iter().enumerate() {
+                                if let Some(label) = graph.find_node_label(&rank.id) {
+                                    interaction.log(format!(
+                                        "{:>4}  {:>5}  {:>11.2}  {:>8.3}  {}: {}",
+                                        i + 1,
+                                        rank.reach,
+                                        rank.betweenness,
+                                        rank.page_rank,
+                                        rank.id,
+                                        label
+                                    ));
+                                }
+                            }
+                            false
+                        }
+                        Command::History => {
+                            let Some(events) = events else {
+                                interaction.log(NO_EVENT_LOG);
+                                continue;
+                            };
+                            if let Err(e) = show_history(interaction, events) {
+                                interaction.log(format!("({:#})", e));
+                            }
+                            false
+                        }
+                        Command::At { moment } => {
+                            let Some(events) = events else {
+                                interaction.log(NO_EVENT_LOG);
+                                continue;
+                            };
+                            if let Err(e) = show_past(interaction, events, json_file, moment) {
+                                interaction.log(format!("({:#})", e));
+                            }
+                            false
+                        }
+                        Command::Restore { step } => {
+                            let Some(events) = events else {
+                                interaction.log(NO_EVENT_LOG);
+                                continue;
+                            };
+                            match events.restore(step) {
+                                Ok(restored) => {
+                                    *graph.write().unwrap() = restored;
+                                    interaction
+                                        .log(format!("(restored the graph to step {})", step));
+                                    true
+                                }
+                                Err(e) => {
+                                    interaction.log(format!("({})", e));
+                                    false
+                                }
+                            }
+                        }
                     }
                 }
-            }
-            Err(ReadlineError::Interrupted) => {
-                interaction.log("CTRL-C");
+                Err(ReadlineError::Interrupted) => {
+                    interaction.log("CTRL-C");
 
-                return Ok(());
-            }
-            Err(ReadlineError::Eof) => {
-                interaction.log("CTRL-D");
+                    return Ok(());
+                }
+                Err(ReadlineError::Eof) => {
+                    interaction.log("CTRL-D");
 
-                return Ok(());
-            }
-            Err(err) => {
-                interaction.log(format!("Error: {:?}", err));
-                return Err(anyhow::anyhow!("readline error: {}", err.to_string()));
-            }
-        };
+                    return Ok(());
+                }
+                Err(err) => {
+                    interaction.log(format!("Error: {:?}", err));
+                    return Err(anyhow::anyhow!("readline error: {}", err));
+                }
+            };
 
-        if dirty {
-            compile_graph(interaction, json_file, &graph, Some(&reload_tx))?;
+            if dirty {
+                watch.save(|| {
+                    compile_graph(
+                        interaction,
+                        json_file,
+                        store,
+                        name,
+                        &graph,
+                        Some(&reload_tx),
+                    )?;
+                    store.read(name)
+                })?;
+            }
         }
-    }
+    })
 }
 
+/// how many steps `history` shows.
+const HISTORY_LENGTH: usize = 20;
+
+const NO_EVENT_LOG: &str =
+    "(history is only kept in the event log; start microdot with --events to use it)";
+
 enum RenderMethod {
     GraphViz,
 }
 
 const RENDER_METHOD: RenderMethod = RenderMethod::GraphViz;
 
+fn group_name(group: Option<HashTag>, grouping: CostGrouping) -> String {
+    match (group, grouping) {
+        (Some(group), _) => group.to_string(),
+        (None, CostGrouping::Subgraph) => "(no subgraph)".to_string(),
+        (None, CostGrouping::HashTag) => "(no hashtag)".to_string(),
+    }
+}
+
+/// lists the last few steps in the event log.
+fn show_history<I: Interaction>(interaction: &mut I, events: &EventLog) -> Result<()> {
+    let history = events.events()?;
+    let skipped = history.len().saturating_sub(HISTORY_LENGTH);
+    if skipped > 0 {
+        interaction.log(format!("({} earlier steps not shown)", skipped));
+    }
+    for (i, event) in history.iter().enumerate().skip(skipped) {
+        interaction.log(format!(
+            "{:>4}  {}  {}",
+            i + 1,
+            format_timestamp(event.at),
+            event.change.to_help_string()
+        ));
+    }
+    Ok(())
+}
+
+/// renders the graph as it was at `moment` beside the graph file, leaving the graph alone.
+fn show_past<I: Interaction>(
+    interaction: &mut I,
+    events: &EventLog,
+    json_file: &Path,
+    moment: Moment,
+) -> Result<()> {
+    let step = events.step_of(moment)?;
+    if step > events.len() {
+        return Err(anyhow!(
+            "there are only {} steps in the history",
+            events.len()
+        ));
+    }
+    let past = events.replay_to(step)?;
+    let past_file = json_file.with_extension(format!("at-{}.json", step));
+    let dot_file = save_dot_file(&past_file, &past)?;
+    if interaction.should_compile() {
+        interaction.log(format!("({})", compile_dot(dot_file, None)));
+    } else {
+        interaction.log(format!(
+            "(saved step {} to {})",
+            step,
+            dot_file.to_string_lossy()
+        ));
+    }
+    Ok(())
+}
+
+/// if someone else has changed the saved graph, asks what to do about it, then saves and renders
+/// whatever was chosen. Returns whether the graph was replaced by one whose ids mean something
+/// else.
+fn check_external_changes<I: Interaction>(
+    interaction: &mut I,
+    json_file: &Path,
+    store: &dyn Store,
+    name: &str,
+    graph: &Arc<RwLock<Graph>>,
+    reload_tx: &UnboundedSender<Update>,
+    watch: &Watch,
+) -> Result<bool> {
+    watch.take_changed();
+    let Some(saved) = watch.last_saved() else {
+        return Ok(false);
+    };
+    let renumbered = reload_external_changes(interaction, store, name, graph, &saved)?;
+    if store.read(name)?.as_ref() != Some(&saved) {
+        watch.save(|| {
+            compile_graph(interaction, json_file, store, name, graph, Some(reload_tx))?;
+            store.read(name)
+        })?;
+    }
+    Ok(renumbered)
+}
+
+/// What each node and edge id refers to in a graph.
+#[derive(Default)]
+struct Ids {
+    nodes: HashMap<Id, Label>,
+    edges: HashMap<Id, (Id, Id)>,
+}
+
+impl Exporter for Ids {
+    fn set_direction(&mut self, _is_left_right: bool) {}
+
+    fn add_node(&mut self, id: &Id, label: &Label, _highlight: NodeHighlight) {
+        self.nodes.insert(id.clone(), label.clone());
+    }
+
+    fn add_edge(&mut self, id: &Id, from: &Id, to: &Id) {
+        self.edges.insert(id.clone(), (from.clone(), to.clone()));
+    }
+}
+
+impl Ids {
+    fn of(graph: &Graph) -> Self {
+        let mut ids = Self::default();
+        graph.export(&mut ids);
+        ids
+    }
+
+    /// whether every id in both graphs refers to the same node, or the same edge, in each. Ids
+    /// only one of them has are fine; a command using one just fails.
+    fn agree_with(&self, other: &Ids) -> bool {
+        self.nodes
+            .iter()
+            .all(|(id, label)| other.nodes.get(id).is_none_or(|other| other == label))
+            && self
+                .edges
+                .iter()
+                .all(|(id, ends)| other.edges.get(id).is_none_or(|other| other == ends))
+    }
+}
+
+/// checks whether someone else has changed the saved graph since we last saved it, and if so
+/// offers to reload it; or, if our graph has changed too, to merge the two. Opening a graph
+/// renumbers it, and merging can give nodes new ids, so this returns whether the graph was
+/// replaced by one in which some ids refer to something else.
+fn reload_external_changes<I: Interaction>(
+    interaction: &mut I,
+    store: &dyn Store,
+    name: &str,
+    graph: &Arc<RwLock<Graph>>,
+    last_saved: &str,
+) -> Result<bool> {
+    let Some(theirs) = store.read(name)?.filter(|theirs| theirs != last_saved) else {
+        return Ok(false);
+    };
+    let ours = JsonExporter::new().export_json(&graph.read().unwrap());
+    if theirs == ours {
+        // saved by someone sharing this graph, like the web api.
+        return Ok(false);
+    }
+
+    let replacement = if ours == last_saved {
+        let answer = interaction
+            .read(&format!("{} has changed on disk. Reload it? [Y/n] ", name))
+            .unwrap_or_default();
+        if answer.trim().to_lowercase().starts_with('n') {
+            interaction.log(format!("(keeping this graph; it will replace {})", name));
+            return Ok(false);
+        }
+        theirs
+    } else {
+        let answer = interaction
+            .read(&format!(
+                "{} has changed on disk, and so has this graph. [M]erge, [r]eload, or [k]eep this one? ",
+                name
+            ))
+            .unwrap_or_default();
+        match answer.trim().to_lowercase().chars().next() {
+            Some('k') => {
+                interaction.log(format!("(keeping this graph; it will replace {})", name));
+                return Ok(false);
+            }
+            Some('r') => theirs,
+            _ => {
+                let merged = merge(last_saved, &ours, &theirs)?;
+                for conflict in &merged.conflicts {
+                    interaction.log(format!("(conflict: {})", conflict));
+                }
+                merged.json
+            }
+        }
+    };
+
+    let reloaded = JsonImporter::new(replacement).import()?;
+    interaction.log(format!("(reloaded {}: {})", name, reloaded));
+    let mut graph = graph.write().unwrap();
+    let renumbered = !Ids::of(&graph).agree_with(&Ids::of(&reloaded));
+    *graph = reloaded;
+    Ok(renumbered)
+}
+
+/// saves and renders the graph.
 fn compile_graph<I: Interaction>(
     interaction: &mut I,
     json_file: &Path,
+    store: &dyn Store,
+    name: &str,
     graph: &Arc<RwLock<Graph>>,
-    reload_tx: Option<&UnboundedSender<()>>,
+    reload_tx: Option<&UnboundedSender<Update>>,
 ) -> Result<()> {
     let graph = match graph.write() {
         Ok(graph) => graph,
         Err(e) => return Err(anyhow!(e.to_string())),
     };
+    save_and_render(
+        json_file,
+        store,
+        name,
+        &graph,
+        interaction.should_compile(),
+        reload_tx,
+    )
+}
+
+/// saves the graph, writes its dot file and, if `compile`, renders that to svg.
+pub(crate) fn save_and_render(
+    json_file: &Path,
+    store: &dyn Store,
+    name: &str,
+    graph: &Graph,
+    compile: bool,
+    reload_tx: Option<&UnboundedSender<Update>>,
+) -> Result<()> {
+    // a read-only graph is still rendered, for looking at, but never saved.
+    if store.read_only().is_none() {
+        store.write(name, &JsonExporter::new().export_json(graph))?;
+    }
     match RENDER_METHOD {
         RenderMethod::GraphViz => {
             // causes problems in unit tests, because interim results have the file saving partial
             // results, which tells cargo watch that it should recompile
-            let interactive_dot_file = save_dot_file(json_file, &graph)?;
-            if interaction.should_compile() {
+            let interactive_dot_file = save_dot_file(json_file, graph)?;
+            if compile {
                 compile_dot(interactive_dot_file, reload_tx);
             }
         }
@@ -180,10 +601,6 @@ fn compile_graph<I: Interaction>(
 }
 
 fn save_dot_file(json_file: &Path, graph: &Graph) -> Result<PathBuf> {
-    let mut json_exporter = JsonExporter::new();
-    let json = json_exporter.export_json(graph);
-    write_if_different(json_file, json)?;
-
     let mut dot_exporter = GraphVizExporter::new(DisplayMode::Interactive);
     let interactive_dot = dot_exporter.export_dot(graph);
     let interactive_dot_file = json_file.with_extension("dot");
@@ -192,12 +609,15 @@ fn save_dot_file(json_file: &Path, graph: &Graph) -> Result<PathBuf> {
     Ok(interactive_dot_file)
 }
 
-fn compile_dot(interactive_dot_file: PathBuf, reload_tx: Option<&UnboundedSender<()>>) -> CommandResult {
+fn compile_dot(
+    interactive_dot_file: PathBuf,
+    reload_tx: Option<&UnboundedSender<Update>>,
+) -> CommandResult {
     let svg_compile = graphviz::compile(&interactive_dot_file);
 
-    if let Ok(_) = svg_compile {
+    if svg_compile.is_ok() {
         if let Some(tx) = reload_tx {
-            let _ = tx.send(());
+            let _ = tx.send(Update::Reload);
         }
     }
 
@@ -0,0 +1,230 @@
+use crate::events::EventLog;
+use crate::repl::save_and_render;
+use crate::storage::Store;
+use anyhow::{anyhow, Result};
+use microdot_core::command::GraphCommand;
+use microdot_core::exporter::{Exporter, NodeHighlight};
+use microdot_core::graph::Graph;
+use microdot_core::{CommandResult, Id, Label};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// News of a graph for the web server to pass on to everyone looking at it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Update {
+    /// the diagram has been rendered again.
+    Reload,
+    /// someone changed the graph; `by` is "repl", "api", "jsonl", or the name of whoever is
+    /// editing in a browser.
+    Applied {
+        by: String,
+        command: GraphCommand,
+        result: String,
+    },
+}
+
+/// The ids of nodes and edges; those a command created, or those in a graph.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Created {
+    pub nodes: Vec<Id>,
+    pub edges: Vec<Id>,
+}
+
+impl Exporter for Created {
+    fn set_direction(&mut self, _is_left_right: bool) {}
+
+    fn add_node(&mut self, id: &Id, _label: &Label, _highlight: NodeHighlight) {
+        self.nodes.push(id.clone());
+    }
+
+    fn add_edge(&mut self, id: &Id, _from: &Id, _to: &Id) {
+        self.edges.push(id.clone());
+    }
+}
+
+impl Created {
+    fn of(graph: &Graph) -> Self {
+        let mut ids = Self::default();
+        graph.export(&mut ids);
+        ids
+    }
+
+    /// the ids which weren't in `before`.
+    fn since(self, before: &Created) -> Self {
+        Self {
+            nodes: self
+                .nodes
+                .into_iter()
+                .filter(|id| !before.nodes.contains(id))
+                .collect(),
+            edges: self
+                .edges
+                .into_iter()
+                .filter(|id| !before.edges.contains(id))
+                .collect(),
+        }
+    }
+}
+
+/// A graph being edited, shared with the repl, and where it's saved and rendered after every
+/// change; what the web server needs to change the graph the way the repl does.
+#[derive(Clone)]
+pub struct Session {
+    pub graph: Arc<RwLock<Graph>>,
+    pub(crate) json_file: PathBuf,
+    pub(crate) store: Arc<dyn Store>,
+    pub(crate) name: String,
+    pub(crate) events: Option<Arc<EventLog>>,
+    pub(crate) reload_tx: UnboundedSender<Update>,
+    compile: bool,
+}
+
+impl Session {
+    pub fn new(
+        graph: Arc<RwLock<Graph>>,
+        json_file: PathBuf,
+        store: Arc<dyn Store>,
+        name: String,
+        reload_tx: UnboundedSender<Update>,
+    ) -> Self {
+        Self {
+            graph,
+            json_file,
+            store,
+            name,
+            events: None,
+            reload_tx,
+            compile: true,
+        }
+    }
+
+    /// record every command in an event log too.
+    pub fn with_events(self, events: Option<Arc<EventLog>>) -> Self {
+        Self { events, ..self }
+    }
+
+    /// whether to render the dot file to svg after every change; it always is unless this is
+    /// turned off.
+    pub fn compiling(self, compile: bool) -> Self {
+        Self { compile, ..self }
+    }
+
+    /// applies a command on behalf of `by`, records it in the event log if there is one, tells
+    /// everyone about it, then saves and renders the graph. Commands are applied one at a time,
+    /// whoever they come from.
+    pub fn apply(&self, by: &str, command: GraphCommand) -> Result<CommandResult> {
+        self.apply_reporting(by, command)
+            .map(|(result, _created)| result)
+    }
+
+    /// like `apply`, also saying which nodes and edges the command created.
+    pub fn apply_reporting(
+        &self,
+        by: &str,
+        command: GraphCommand,
+    ) -> Result<(CommandResult, Created)> {
+        if let Some(reason) = self.read_only() {
+            return Err(anyhow!(reason.to_string()));
+        }
+        let mut graph = self.graph.write().unwrap();
+        let before = Created::of(&graph);
+        let result = graph.apply_command(command.clone());
+        let created = Created::of(&graph).since(&before);
+        if let Some(events) = &self.events {
+            events.append(&command, &graph)?;
+        }
+        let _ = self.reload_tx.send(Update::Applied {
+            by: by.to_string(),
+            command,
+            result: result.to_string(),
+        });
+        save_and_render(
+            &self.json_file,
+            self.store.as_ref(),
+            &self.name,
+            &graph,
+            self.compile,
+            Some(&self.reload_tx),
+        )?;
+        Ok((result, created))
+    }
+
+    /// why the graph can't be changed, if it's only open to look at.
+    pub fn read_only(&self) -> Option<&str> {
+        self.store.read_only()
+    }
+
+    /// the graph as it was last saved, as JSON; `None` if it hasn't been saved yet.
+    pub fn saved_json(&self) -> Result<Option<String>> {
+        self.store.read(&self.name)
+    }
+
+    /// saves and renders the graph as it is, so there's a diagram of it to serve.
+    pub fn render(&self) -> Result<()> {
+        save_and_render(
+            &self.json_file,
+            self.store.as_ref(),
+            &self.name,
+            &self.graph.read().unwrap(),
+            self.compile,
+            Some(&self.reload_tx),
+        )
+    }
+}
+
+/// a session for a new, empty graph saved as `json_file`; in a `FileStore` unless another `store`
+/// is given, which keeps it under the file's name. Also returns where its updates go.
+#[cfg(test)]
+pub(crate) fn test_session(
+    json_file: &std::path::Path,
+    store: Option<Box<dyn Store>>,
+) -> (Session, tokio::sync::mpsc::UnboundedReceiver<Update>) {
+    let (file_store, name) = crate::storage::FileStore::for_file(json_file);
+    let store = store.unwrap_or_else(|| Box::new(file_store));
+    let (reload_tx, reload_rx) = tokio::sync::mpsc::unbounded_channel();
+    let session = Session::new(
+        Arc::new(RwLock::new(Graph::new())),
+        json_file.to_path_buf(),
+        Arc::from(store),
+        name,
+        reload_tx,
+    );
+    (session, reload_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{FileStore, ReadOnlyStore};
+
+    #[test]
+    fn read_only_sessions_refuse_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("locked.json");
+        let (store, name) = FileStore::for_file(&json_file);
+        store.write(&name, "{}").unwrap();
+        let store = ReadOnlyStore::new(Box::new(store), "locked.json is locked by someone else");
+        let (session, mut reload_rx) = test_session(&json_file, Some(Box::new(store)));
+
+        let refused = session.apply(
+            "api",
+            GraphCommand::InsertNode {
+                label: Label::new("mine"),
+            },
+        );
+        assert_eq!(
+            refused.unwrap_err().to_string(),
+            "locked.json is locked by someone else"
+        );
+        assert_eq!(
+            session.graph.read().unwrap().to_string(),
+            "Graph: 0 nodes, 0 edges"
+        );
+        assert!(reload_rx.try_recv().is_err());
+        assert_eq!(session.saved_json().unwrap(), Some("{}".to_string()));
+        assert!(session.store.write(&session.name, "[]").is_err());
+    }
+}
@@ -1,40 +1,421 @@
-use crate::util::write_if_different;
-use anyhow::{Context, Result};
+use crate::util::{temp_file_in, write_if_different};
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-trait Store {
-    fn read<P: AsRef<Path>>(&self, path: P) -> Result<String>;
-    fn write<P: AsRef<Path>, S: AsRef<str>>(&self, path: P, content: S) -> Result<()>;
+/// Somewhere to keep graphs, as JSON, by name. Stores are shared between the repl and the web
+/// server.
+pub trait Store: Send + Sync {
+    /// the graph's JSON, or `None` if nothing has been saved under that name yet.
+    fn read(&self, name: &str) -> Result<Option<String>>;
+    fn write(&self, name: &str, content: &str) -> Result<()>;
+    fn names(&self) -> Result<Vec<String>>;
+    /// every saved version of the graph, oldest first. Stores without history return just the
+    /// current version.
+    fn history(&self, name: &str) -> Result<Vec<Revision>>;
+    /// why the graphs can't be changed, if they can only be looked at.
+    fn read_only(&self) -> Option<&str> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revision {
+    pub number: usize,
+    /// seconds since the unix epoch, when known.
+    pub saved_at: Option<u64>,
+    pub content: String,
 }
 
-struct FileStore {
+/// where a `FileStore` keeps earlier versions of its graphs.
+pub const BACKUP_DIR: &str = ".microdot-backups";
+
+/// Graphs kept as JSON files in a directory; the name is the file name. Optionally keeps the last
+/// few versions of each graph in `.microdot-backups/`.
+pub struct FileStore {
     root: PathBuf,
+    backups: usize,
 }
 
 impl FileStore {
-    #[allow(dead_code)]
-    fn new(root: PathBuf) -> Self {
-        Self { root }
+    pub fn new(root: PathBuf) -> Self {
+        Self { root, backups: 0 }
+    }
+
+    /// before a graph is overwritten, copy the old version into the backup directory, keeping at
+    /// most `backups` of them.
+    pub fn keeping_backups(self, backups: usize) -> Self {
+        Self { backups, ..self }
+    }
+
+    /// a store for one file, and the name that file has in the store.
+    pub fn for_file(file: &Path) -> (Self, String) {
+        let root = file
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_else(|| PathBuf::from("."));
+        let name = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        (Self::new(root), name)
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    fn backup_dir(&self) -> PathBuf {
+        self.root.join(BACKUP_DIR)
+    }
+
+    /// the backups of a graph, oldest first, with when they were made in milliseconds since the
+    /// unix epoch.
+    fn backups(&self, name: &str) -> Result<Vec<(u64, PathBuf)>> {
+        let backup_dir = self.backup_dir();
+        if !backup_dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let prefix = format!("{}.", name);
+        let mut backups = vec![];
+        for entry in std::fs::read_dir(&backup_dir)? {
+            let path = entry?.path();
+            let saved_at = path
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .and_then(|file_name| file_name.strip_prefix(&prefix))
+                .and_then(|millis| millis.parse::<u64>().ok());
+            if let Some(saved_at) = saved_at {
+                backups.push((saved_at, path));
+            }
+        }
+        backups.sort();
+        Ok(backups)
+    }
+
+    fn back_up(&self, name: &str, content: &str) -> Result<()> {
+        let backup_dir = self.backup_dir();
+        std::fs::create_dir_all(&backup_dir)?;
+        let mut millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        // two saves in the same millisecond mustn't overwrite each other's backup, so a backup
+        // whose name is taken moves on to the next millisecond.
+        let mut backup = temp_file_in(&backup_dir, &self.path(name), content.as_bytes())?;
+        loop {
+            let path = backup_dir.join(format!("{}.{}", name, millis));
+            match backup.persist_noclobber(path) {
+                Ok(_) => break,
+                Err(e) if e.error.kind() == ErrorKind::AlreadyExists => {
+                    backup = e.file;
+                    millis += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let backups = self.backups(name)?;
+        let excess = backups.len().saturating_sub(self.backups);
+        for (_, path) in backups.into_iter().take(excess) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
     }
 }
 
 impl Store for FileStore {
-    fn read<P: AsRef<Path>>(&self, path: P) -> Result<String> {
-        std::fs::read_to_string(path).with_context(|| {
+    fn read(&self, name: &str) -> Result<Option<String>> {
+        let path = self.path(name);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let content = std::fs::read_to_string(&path).with_context(|| {
             format!(
                 "reading from file store rooted at {}",
                 self.root.to_string_lossy()
             )
-        })
+        })?;
+        Ok(Some(content))
     }
 
-    fn write<P: AsRef<Path>, S: AsRef<str>>(&self, path: P, content: S) -> Result<()> {
-        let bytes = content.as_ref().as_bytes();
-        write_if_different(path, bytes).with_context(|| {
+    fn write(&self, name: &str, content: &str) -> Result<()> {
+        if self.backups > 0 {
+            if let Some(previous) = self.read(name)?.filter(|previous| previous != content) {
+                self.back_up(name, &previous)
+                    .with_context(|| format!("backing up {}", name))?;
+            }
+        }
+
+        write_if_different(self.path(name), content.as_bytes()).with_context(|| {
             format!(
                 "writing to file store rooted at {}",
                 self.root.to_string_lossy()
             )
         })
     }
+
+    fn names(&self) -> Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                if let Some(name) = path.file_name() {
+                    names.push(name.to_string_lossy().to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn history(&self, name: &str) -> Result<Vec<Revision>> {
+        let mut contents = vec![];
+        for (millis, path) in self.backups(name)? {
+            contents.push((Some(millis / 1000), std::fs::read_to_string(path)?));
+        }
+        if let Some(current) = self.read(name)? {
+            let saved_at = std::fs::metadata(self.path(name))?
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            contents.push((saved_at, current));
+        }
+
+        let revisions = contents
+            .into_iter()
+            .enumerate()
+            .map(|(i, (saved_at, content))| Revision {
+                number: i + 1,
+                saved_at,
+                content,
+            })
+            .collect();
+        Ok(revisions)
+    }
+}
+
+/// Any number of named graphs in a single SQLite database, keeping every saved version.
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let connection = Connection::open(path)
+            .with_context(|| format!("opening sqlite store at {}", path.to_string_lossy()))?;
+        Self::from_connection(connection)
+    }
+
+    pub fn in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self> {
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS revisions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                content TEXT NOT NULL,
+                saved_at INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS revisions_by_name ON revisions (name, id);",
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+        })
+    }
+}
+
+impl Store for SqliteStore {
+    fn read(&self, name: &str) -> Result<Option<String>> {
+        let content = self
+            .connection
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT content FROM revisions WHERE name = ?1 ORDER BY id DESC LIMIT 1",
+                params![name],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(content)
+    }
+
+    fn write(&self, name: &str, content: &str) -> Result<()> {
+        // like `write_if_different`; saving an unchanged graph doesn't add to the history.
+        if self.read(name)?.as_deref() == Some(content) {
+            return Ok(());
+        }
+
+        let saved_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO revisions (name, content, saved_at) VALUES (?1, ?2, ?3)",
+            params![name, content, saved_at],
+        )?;
+        Ok(())
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement =
+            connection.prepare("SELECT DISTINCT name FROM revisions ORDER BY name")?;
+        let names = statement
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        Ok(names)
+    }
+
+    fn history(&self, name: &str) -> Result<Vec<Revision>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection
+            .prepare("SELECT content, saved_at FROM revisions WHERE name = ?1 ORDER BY id")?;
+        let rows = statement
+            .query_map(params![name], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let revisions = rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, (content, saved_at))| Revision {
+                number: i + 1,
+                saved_at: Some(saved_at),
+                content,
+            })
+            .collect();
+        Ok(revisions)
+    }
+}
+
+/// Another store's graphs, which can be read but not changed; writes fail, saying `reason`. For
+/// looking at a graph while someone else has it locked.
+pub struct ReadOnlyStore {
+    inner: Box<dyn Store>,
+    reason: String,
+}
+
+impl ReadOnlyStore {
+    pub fn new(inner: Box<dyn Store>, reason: impl ToString) -> Self {
+        Self {
+            inner,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Store for ReadOnlyStore {
+    fn read(&self, name: &str) -> Result<Option<String>> {
+        self.inner.read(name)
+    }
+
+    fn write(&self, name: &str, _content: &str) -> Result<()> {
+        Err(anyhow!("couldn't save {}: {}", name, self.reason))
+    }
+
+    fn names(&self) -> Result<Vec<String>> {
+        self.inner.names()
+    }
+
+    fn history(&self, name: &str) -> Result<Vec<Revision>> {
+        self.inner.history(name)
+    }
+
+    fn read_only(&self) -> Option<&str> {
+        Some(&self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store_reads_and_writes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, name) = FileStore::for_file(&dir.path().join("graph.json"));
+        assert_eq!(name, "graph.json");
+        assert_eq!(store.read(&name).unwrap(), None);
+
+        store.write(&name, "{}").unwrap();
+        assert_eq!(store.read(&name).unwrap(), Some("{}".to_string()));
+        assert_eq!(store.names().unwrap(), vec!["graph.json".to_string()]);
+        assert_eq!(store.history(&name).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn file_store_keeps_the_last_few_versions_as_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, name) = FileStore::for_file(&dir.path().join("graph.json"));
+        let store = store.keeping_backups(2);
+
+        // saved quickly enough that some backups are made in the same millisecond.
+        for version in ["v1", "v2", "v2", "v3", "v4"] {
+            store.write(&name, version).unwrap();
+        }
+
+        assert_eq!(store.backups(&name).unwrap().len(), 2);
+        assert_eq!(store.names().unwrap(), vec!["graph.json".to_string()]);
+        let history: Vec<_> = store
+            .history(&name)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.number, r.content))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (1, "v2".to_string()),
+                (2, "v3".to_string()),
+                (3, "v4".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn sqlite_store_keeps_named_graphs_with_history() {
+        let store = SqliteStore::in_memory().unwrap();
+        store.write("one", "v1").unwrap();
+        store.write("one", "v2").unwrap();
+        store.write("one", "v2").unwrap();
+        store.write("two", "other").unwrap();
+
+        assert_eq!(store.read("one").unwrap(), Some("v2".to_string()));
+        assert_eq!(store.read("missing").unwrap(), None);
+        assert_eq!(
+            store.names().unwrap(),
+            vec!["one".to_string(), "two".to_string()]
+        );
+
+        let history: Vec<_> = store
+            .history("one")
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.number, r.content))
+            .collect();
+        assert_eq!(history, vec![(1, "v1".to_string()), (2, "v2".to_string())]);
+    }
+
+    #[test]
+    fn sqlite_store_persists_to_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("graphs.sqlite");
+        SqliteStore::open(&path)
+            .unwrap()
+            .write("g", "saved")
+            .unwrap();
+        let reopened = SqliteStore::open(&path).unwrap();
+        assert_eq!(reopened.read("g").unwrap(), Some("saved".to_string()));
+    }
 }
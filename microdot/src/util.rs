@@ -1,13 +1,18 @@
 use crate::graphviz::{compile, DisplayMode, GraphVizExporter};
 use crate::repl::repl;
+use crate::session::Session;
+use crate::storage::FileStore;
 use crate::Interaction;
 use anyhow::{anyhow, Context, Result};
 use microdot_core::graph::Graph;
 use std::collections::VecDeque;
+use std::io::Write;
 use std::path::*;
 use std::sync::{Arc, RwLock};
-use unfold::Unfold;
+use std::time::SystemTime;
+use tempfile::{Builder, NamedTempFile};
 use tokio::sync::mpsc;
+use unfold::Unfold;
 
 pub fn git_root() -> Result<PathBuf> {
     let current_dir: &Path = &std::env::current_dir()?;
@@ -45,7 +50,15 @@ pub fn compile_input_string_content(text_file: PathBuf) -> PathBuf {
         .join(text_file.file_name().unwrap());
 
     let (reload_tx, _reload_rx) = mpsc::unbounded_channel();
-    repl(&mut auto_interaction, &tmp_json, graph.clone(), reload_tx).expect("error in repl");
+    let (store, name) = FileStore::for_file(&tmp_json);
+    let session = Session::new(
+        graph.clone(),
+        tmp_json.clone(),
+        Arc::new(store),
+        name,
+        reload_tx,
+    );
+    repl(&mut auto_interaction, &session).expect("error in repl");
 
     let temp_json = std::fs::read_to_string(&tmp_json).expect("could not read json file");
     let final_json_path = text_file.with_extension("json");
@@ -77,13 +90,49 @@ pub fn write_if_different<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C)
     };
 
     if needs_write {
-        std::fs::write(path, contents)
+        write_atomically(path, contents)
             .with_context(|| format!("could not write to file '{}'", path.to_string_lossy()))
     } else {
         Ok(())
     }
 }
 
+/// writes to a temporary file beside `path` and renames it into place, so a crash or a full disc
+/// leaves either the old content or the new, never half of one.
+pub fn write_atomically<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> Result<()> {
+    // a symlinked file is saved where the link points, and the link is left alone.
+    let path = std::fs::canonicalize(path.as_ref()).unwrap_or_else(|_| path.as_ref().into());
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    // every save gets a temporary file of its own, so saves from the repl and the web server at
+    // the same time can't write into each other's; it's removed if anything goes wrong.
+    let tmp = temp_file_in(dir, &path, contents.as_ref())?;
+    tmp.persist(&path)?;
+    Ok(())
+}
+
+/// a temporary file in `dir` holding `contents`, ready to be renamed into place. Temporary files
+/// are only for their owner, but what they become may be shared, so it takes the permissions of
+/// `like` if that exists, or those any other new file would get.
+pub(crate) fn temp_file_in(dir: &Path, like: &Path, contents: &[u8]) -> Result<NamedTempFile> {
+    let mut builder = Builder::new();
+    #[cfg(unix)]
+    builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o666));
+    let mut tmp = builder.tempfile_in(dir)?;
+    if let Ok(existing) = std::fs::metadata(like) {
+        tmp.as_file().set_permissions(existing.permissions())?;
+    }
+    tmp.write_all(contents)?;
+    // a new file can get a coarser timestamp than the one it replaces, which would hide the
+    // change from anything watching modification times.
+    tmp.as_file().set_modified(SystemTime::now())?;
+    tmp.as_file().sync_all()?;
+    Ok(tmp)
+}
+
 struct AutoInteraction {
     lines: VecDeque<String>,
     log: String,
@@ -148,6 +197,10 @@ macro_rules! hashmap {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::events::EventLog;
+    use crate::json::{JsonExporter, JsonImporter};
+    use crate::session::test_session;
+    use crate::storage::ReadOnlyStore;
     use std::fs::File;
     use std::io::Read;
     use std::path::PathBuf;
@@ -206,4 +259,367 @@ mod test {
 
         assert_eq!(contents, read_contents);
     }
+
+    #[test]
+    fn write_if_different_should_not_leave_temporary_files() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+
+        write_if_different(&path, "old content").unwrap();
+        write_if_different(&path, "new content").unwrap();
+
+        check_content_equal(&path, "new content");
+        let files: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["test.txt"]);
+    }
+
+    #[test]
+    fn saves_at_the_same_time_do_not_mix() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.txt");
+        let contents: Vec<String> = (0..8).map(|i| i.to_string().repeat(100_000)).collect();
+
+        std::thread::scope(|scope| {
+            for content in &contents {
+                let path = &path;
+                scope.spawn(move || write_atomically(path, content).unwrap());
+            }
+        });
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains(&saved));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_keep_the_permissions_of_the_file_they_replace() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("shared.json");
+        std::fs::write(&path, "old content").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o664)).unwrap();
+
+        write_atomically(&path, "new content").unwrap();
+
+        check_content_equal(&path, "new content");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o664);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saves_through_symlinks() {
+        let dir = tempdir().unwrap();
+        let target = dir.path().join("target.json");
+        let link = dir.path().join("link.json");
+        std::fs::write(&target, "old content").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write_atomically(&link, "new content").unwrap();
+
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        check_content_equal(&target, "new content");
+    }
+
+    #[test]
+    fn event_log_replays_a_repl_session() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("session.json");
+        let events = Arc::new(EventLog::for_file(&json_file).unwrap());
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let session = session.with_events(Some(events.clone()));
+        let graph = session.graph.clone();
+        let lines = ["i first", "i second", "l n0 n1", "d n0", "i third", "lr"]
+            .map(String::from)
+            .into();
+
+        repl(&mut AutoInteraction::new(lines), &session).unwrap();
+
+        assert_eq!(events.len(), 6);
+        let replayed = events.replay().unwrap();
+        assert_eq!(
+            JsonExporter::new().export_json(&replayed),
+            JsonExporter::new().export_json(&graph.read().unwrap())
+        );
+    }
+
+    #[test]
+    fn time_travel_through_the_event_log() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("plan.json");
+        let events = Arc::new(EventLog::for_file(&json_file).unwrap());
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let session = session.with_events(Some(events.clone()));
+        let graph = session.graph.clone();
+        let lines = ["i first", "i second", "at 1", "restore 1", "history"]
+            .map(String::from)
+            .into();
+        let mut interaction = AutoInteraction::new(lines);
+
+        repl(&mut interaction, &session).unwrap();
+
+        assert!(dir.path().join("plan.at-1.dot").exists());
+        assert_eq!(graph.read().unwrap().to_string(), "Graph: 1 nodes, 0 edges");
+        let log = interaction.log();
+        assert!(log.contains("(restored the graph to step 1)"), "{}", log);
+        assert!(log.contains("Restore the graph to step 1"), "{}", log);
+    }
+
+    #[test]
+    fn time_travel_mistakes_are_reported_without_ending_the_session() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("plan.json");
+        let events = Arc::new(EventLog::for_file(&json_file).unwrap());
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let session = session.with_events(Some(events.clone()));
+        let graph = session.graph.clone();
+        let log_file = json_file.with_extension("events.jsonl");
+        let corrupt = move || {
+            let mut log = std::fs::read_to_string(&log_file).unwrap();
+            log.push_str("not an event\n");
+            std::fs::write(&log_file, log).unwrap();
+        };
+        let mut interaction = MeddlingInteraction {
+            lines: ["i first", "at 5", "restore 99", "history", "i second"]
+                .map(String::from)
+                .into(),
+            meddling: [None, None, None, Some(Box::new(corrupt) as Meddle)]
+                .into_iter()
+                .collect(),
+            log: String::new(),
+        };
+
+        repl(&mut interaction, &session).unwrap();
+
+        let log = &interaction.log;
+        assert!(
+            log.contains("(there are only 1 steps in the history)"),
+            "{}",
+            log
+        );
+        assert!(log.contains("reading event 2 of"), "{}", log);
+        assert_eq!(labels(&graph.read().unwrap()), "first, second");
+    }
+
+    #[test]
+    fn cost_breaks_mixed_values_down_by_type() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("mixed.json");
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let lines = ["i a $t=1h", "i b $t=2", "i c $t=3", "cost t", "cost t sum"]
+            .map(String::from)
+            .into();
+        let mut interaction = AutoInteraction::new(lines);
+
+        repl(&mut interaction, &session).unwrap();
+
+        // a plain `cost` reads as it always has; naming the aggregation uses its name.
+        let log = interaction.log();
+        assert!(
+            log.contains(
+                "performing cost analysis using variable t\nTotal cost: numbers: 5; times: 1 hour"
+            ),
+            "{}",
+            log
+        );
+        assert!(
+            log.contains("finding the sum of variable t\nOverall sum: numbers: 5; times: 1 hour"),
+            "{}",
+            log
+        );
+    }
+
+    type Meddle = Box<dyn FnOnce()>;
+
+    /// types lines into the repl, doing something else - like editing the file - before some of
+    /// them.
+    struct MeddlingInteraction {
+        lines: VecDeque<String>,
+        meddling: VecDeque<Option<Meddle>>,
+        log: String,
+    }
+
+    impl Interaction for MeddlingInteraction {
+        fn read(&mut self, _prompt: &str) -> rustyline::Result<String> {
+            if let Some(meddle) = self.meddling.pop_front().flatten() {
+                meddle();
+            }
+            self.lines
+                .pop_front()
+                .ok_or(rustyline::error::ReadlineError::Eof)
+        }
+
+        fn add_history<S: AsRef<str> + Into<String>>(&mut self, _history: S) -> bool {
+            true
+        }
+
+        fn log<S: AsRef<str> + Into<String>>(&mut self, message: S) {
+            self.log.push_str(message.as_ref());
+            self.log.push('\n');
+        }
+
+        fn should_compile(&self) -> bool {
+            false
+        }
+    }
+
+    fn add_node_to_file(json_file: &Path, label: &str) {
+        let mut graph = JsonImporter::load(json_file).unwrap();
+        graph.insert_node(microdot_core::Label::new(label));
+        std::fs::write(json_file, JsonExporter::new().export_json(&graph)).unwrap();
+    }
+
+    fn labels(graph: &Graph) -> String {
+        let json = JsonExporter::new().export_json(graph);
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        value["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|n| n["label"].as_str().unwrap())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    #[test]
+    fn reloads_a_file_changed_on_disk() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("plan.json");
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let graph = session.graph.clone();
+        let edited = json_file.clone();
+        let mut interaction = MeddlingInteraction {
+            lines: ["i mine", "i typed meanwhile", "y"]
+                .map(String::from)
+                .into(),
+            meddling: [
+                None,
+                Some(Box::new(move || add_node_to_file(&edited, "theirs")) as Meddle),
+            ]
+            .into_iter()
+            .collect(),
+            log: String::new(),
+        };
+
+        repl(&mut interaction, &session).unwrap();
+
+        // the command typed while the file changed is run on their version, since their change
+        // left every id meaning what it did.
+        assert_eq!(
+            labels(&graph.read().unwrap()),
+            "mine, theirs, typed meanwhile"
+        );
+        assert_eq!(
+            labels(&JsonImporter::load(&json_file).unwrap()),
+            "mine, theirs, typed meanwhile"
+        );
+    }
+
+    #[test]
+    fn commands_typed_before_a_renumbering_reload_are_not_run() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("plan.json");
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let graph = session.graph.clone();
+        let edited = json_file.clone();
+        // someone else deleted 'a' and saved from a fresh session, so 'b' is n0 and 'c' is n1.
+        let renumber = move || {
+            let mut theirs = Graph::new();
+            theirs.insert_node(microdot_core::Label::new("b"));
+            theirs.insert_node(microdot_core::Label::new("c"));
+            std::fs::write(&edited, JsonExporter::new().export_json(&theirs)).unwrap();
+        };
+        let mut interaction = MeddlingInteraction {
+            lines: ["i a", "i b", "i c", "d n1", "y"].map(String::from).into(),
+            meddling: [None, None, None, Some(Box::new(renumber) as Meddle)]
+                .into_iter()
+                .collect(),
+            log: String::new(),
+        };
+
+        repl(&mut interaction, &session).unwrap();
+
+        // 'd n1' meant 'b', but n1 is now 'c'.
+        assert!(
+            interaction
+                .log
+                .contains("its ids have changed, so 'd n1' wasn't run"),
+            "{}",
+            interaction.log
+        );
+        assert_eq!(labels(&graph.read().unwrap()), "b, c");
+    }
+
+    #[test]
+    fn merges_when_both_sides_have_changed() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("plan.json");
+        let (session, _reload_rx) = test_session(&json_file, None);
+        let graph = session.graph.clone();
+        let edited = json_file.clone();
+        let shared = graph.clone();
+        let meddle = move || {
+            // someone changes the file, and the graph is changed elsewhere in this process too.
+            add_node_to_file(&edited, "theirs");
+            shared
+                .write()
+                .unwrap()
+                .insert_node(microdot_core::Label::new("unsaved"));
+        };
+        let mut interaction = MeddlingInteraction {
+            lines: ["i mine", "i typed meanwhile", "m"]
+                .map(String::from)
+                .into(),
+            meddling: [None, Some(Box::new(meddle) as Meddle)]
+                .into_iter()
+                .collect(),
+            log: String::new(),
+        };
+
+        repl(&mut interaction, &session).unwrap();
+
+        assert_eq!(
+            labels(&graph.read().unwrap()),
+            "mine, unsaved, theirs, typed meanwhile"
+        );
+        assert_eq!(
+            labels(&JsonImporter::load(&json_file).unwrap()),
+            "mine, unsaved, theirs, typed meanwhile"
+        );
+    }
+
+    #[test]
+    fn read_only_repl_refuses_commands() {
+        let dir = tempdir().unwrap();
+        let json_file = dir.path().join("locked.json");
+        std::fs::write(&json_file, JsonExporter::new().export_json(&Graph::new())).unwrap();
+        add_node_to_file(&json_file, "theirs");
+        let (store, _) = FileStore::for_file(&json_file);
+        let store = ReadOnlyStore::new(Box::new(store), "locked.json is locked by someone else");
+        let (session, _reload_rx) = test_session(
+            &json_file.with_extension("read-only.json"),
+            Some(Box::new(store)),
+        );
+        let graph = session.graph.clone();
+        *graph.write().unwrap() = JsonImporter::load(&json_file).unwrap();
+        let mut interaction = AutoInteraction::new(["i mine", "/theirs"].map(String::from).into());
+
+        repl(&mut interaction, &session).unwrap();
+
+        let log = interaction.log();
+        assert!(
+            log.contains("(locked.json is locked by someone else; that command wasn't run)"),
+            "{}",
+            log
+        );
+        assert_eq!(labels(&graph.read().unwrap()), "theirs");
+        assert_eq!(labels(&JsonImporter::load(&json_file).unwrap()), "theirs");
+    }
 }
@@ -0,0 +1,145 @@
+use crate::json::JsonExporter;
+use crate::storage::Store;
+use anyhow::Result;
+use microdot_core::graph::Graph;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// how often the saved graph is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Something to tell the person at the repl while it's waiting for them to type.
+pub type Notify = Box<dyn FnMut(String) + Send>;
+
+/// Keeps an eye on a saved graph for changes made by someone else, like an editor or a
+/// `git pull`, while the repl is waiting for the next command.
+#[derive(Default)]
+pub struct Watch {
+    /// what was in the store after we last saved; anything else was put there by someone else.
+    last_saved: Mutex<Option<String>>,
+    changed: AtomicBool,
+}
+
+impl Watch {
+    /// what we last saved, if we've saved yet.
+    pub fn last_saved(&self) -> Option<String> {
+        self.last_saved.lock().unwrap().clone()
+    }
+
+    /// saves with `save`, which returns what's in the store afterwards; the watch waits meanwhile,
+    /// so it never mistakes our own save for someone else's.
+    pub fn save(&self, save: impl FnOnce() -> Result<Option<String>>) -> Result<()> {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        *last_saved = save()?;
+        Ok(())
+    }
+
+    /// whether the watch has seen a change since this was last asked.
+    pub fn take_changed(&self) -> bool {
+        self.changed.swap(false, Ordering::SeqCst)
+    }
+
+    /// checks the store until `stop` is dropped; see `poll`.
+    pub fn run(
+        &self,
+        store: &dyn Store,
+        name: &str,
+        graph: &Arc<RwLock<Graph>>,
+        mut notify: Option<Notify>,
+        stop: Receiver<()>,
+    ) {
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(WATCH_INTERVAL) {
+            self.poll(store, name, graph, notify.as_mut());
+        }
+    }
+
+    /// when someone else has changed the graph, marks a reload as pending and `notify`s whoever
+    /// is at the repl, once. The web view hears about it when whatever they choose is rendered.
+    fn poll(
+        &self,
+        store: &dyn Store,
+        name: &str,
+        graph: &Arc<RwLock<Graph>>,
+        notify: Option<&mut Notify>,
+    ) {
+        if self.changed.load(Ordering::SeqCst) || !self.check(store, name, graph) {
+            return;
+        }
+        self.changed.store(true, Ordering::SeqCst);
+        if let Some(notify) = notify {
+            notify(format!(
+                "({} has changed on disk; press enter to choose what to do about it)\n",
+                name
+            ));
+        }
+    }
+
+    /// whether someone else has changed the saved graph since we last saved it.
+    fn check(&self, store: &dyn Store, name: &str, graph: &Arc<RwLock<Graph>>) -> bool {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        let Some(saved) = last_saved.as_ref() else {
+            return false;
+        };
+        let theirs = match store.read(name) {
+            Ok(Some(theirs)) if &theirs != saved => theirs,
+            _ => return false,
+        };
+        if theirs == JsonExporter::new().export_json(&graph.read().unwrap()) {
+            // saved by someone sharing this graph, like the web api.
+            *last_saved = Some(theirs);
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::FileStore;
+    use microdot_core::Label;
+
+    #[test]
+    fn notices_changes_made_by_someone_else() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, name) = FileStore::for_file(&dir.path().join("watched.json"));
+        let save = |graph: &Graph| {
+            store
+                .write(&name, &JsonExporter::new().export_json(graph))
+                .unwrap()
+        };
+        let graph = Arc::new(RwLock::new(Graph::new()));
+        let watch = Watch::default();
+        watch
+            .save(|| {
+                save(&graph.read().unwrap());
+                store.read(&name)
+            })
+            .unwrap();
+        let notes = Arc::new(Mutex::new(vec![]));
+        let noted = notes.clone();
+        let mut notify: Notify = Box::new(move |note| noted.lock().unwrap().push(note));
+        let mut poll = || watch.poll(&store, &name, &graph, Some(&mut notify));
+
+        // a change from someone sharing the graph isn't news.
+        graph.write().unwrap().insert_node(Label::new("shared"));
+        save(&graph.read().unwrap());
+        poll();
+        assert!(!watch.take_changed());
+        assert_eq!(watch.last_saved(), store.read(&name).unwrap());
+
+        let mut theirs = Graph::new();
+        theirs.insert_node(Label::new("theirs"));
+        save(&theirs);
+        poll();
+        poll();
+        assert!(watch.take_changed());
+        assert!(!watch.take_changed());
+        assert_eq!(
+            *notes.lock().unwrap(),
+            vec!["(watched.json has changed on disk; press enter to choose what to do about it)\n"]
+        );
+    }
+}
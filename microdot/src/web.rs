@@ -1,96 +1,525 @@
-use axum::{http::StatusCode, Router};
-use std::net::SocketAddr;
-use std::path::Path;
-use tower_http::services::ServeFile;
-use crate::graphviz::ImagePage;
+use crate::api;
+use crate::api::{
+    api_error, run_command, ApiError, CommandRequest, CommandResponse, ErrorResponse,
+};
+use crate::auth::{require_same_origin, require_token, Access, Permission, TokenQuery, READ_ONLY};
+use crate::lock::GraphLock;
+use crate::session::{Session, Update};
+use crate::storage::{FileStore, Store};
 use askama::Template;
-use axum::extract::ws::{WebSocket, WebSocketUpgrade, Message};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{self, Query, Request, State};
+use axum::response::{Html, IntoResponse, Response};
 use axum::routing::get;
+use axum::{http::StatusCode, middleware, Extension, Router};
+use futures::{SinkExt, StreamExt};
+use microdot_core::Id;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use futures::{StreamExt, SinkExt};
-use tokio::sync::mpsc::UnboundedReceiver;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::OnceCell;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+/// Everyone connected to `/ws`, and which node each of them has selected.
+#[derive(Default)]
+pub struct Hub {
+    clients: Mutex<BTreeMap<usize, Client>>,
+    next_client: AtomicUsize,
+}
+
+struct Client {
+    name: String,
+    tx: UnboundedSender<Message>,
+    selected: Option<Id>,
+}
 
 // Shared state for websocket clients
-pub type Clients = Arc<Mutex<Vec<tokio::sync::mpsc::UnboundedSender<Message>>>>;
+pub type Clients = Arc<Hub>;
+
+/// What the server sends a browser, besides the changes everyone hears about.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// the diagram, each time it's rendered, for pages to swap in place of the old one.
+    Svg {
+        svg: String,
+    },
+    /// the name this client's changes and selection are shown under.
+    Welcome {
+        you: String,
+    },
+    Selections {
+        selections: Vec<Selection>,
+    },
+    Result(CommandResponse),
+    Error(ErrorResponse),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct Selection {
+    by: String,
+    id: Id,
+}
+
+/// What a browser sends: which node it has selected, like `{"select": "n3"}`, or a command.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ClientMessage {
+    Select(SelectRequest),
+    Command(CommandRequest),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SelectRequest {
+    select: Option<Id>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SocketQuery {
+    name: Option<String>,
+}
+
+impl Hub {
+    /// adds a client, calling it `name` or, failing that, "visitor 1" and so on.
+    fn join(&self, name: Option<String>, tx: UnboundedSender<Message>) -> (usize, String) {
+        let client = self.next_client.fetch_add(1, Ordering::SeqCst) + 1;
+        let name = name
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| format!("visitor {}", client));
+        self.clients.lock().unwrap().insert(
+            client,
+            Client {
+                name: name.clone(),
+                tx,
+                selected: None,
+            },
+        );
+        (client, name)
+    }
+
+    fn leave(&self, client: usize) {
+        self.clients.lock().unwrap().remove(&client);
+        self.broadcast_selections();
+    }
+
+    fn select(&self, client: usize, id: Option<Id>) {
+        if let Some(client) = self.clients.lock().unwrap().get_mut(&client) {
+            client.selected = id;
+        }
+        self.broadcast_selections();
+    }
+
+    fn selections(&self) -> Vec<Selection> {
+        self.clients
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|client| {
+                Some(Selection {
+                    by: client.name.clone(),
+                    id: client.selected.clone()?,
+                })
+            })
+            .collect()
+    }
+
+    fn broadcast_selections(&self) {
+        let selections = ServerMessage::Selections {
+            selections: self.selections(),
+        };
+        self.broadcast(to_message(&selections));
+    }
+
+    fn send(&self, client: usize, message: &ServerMessage) {
+        if let Some(client) = self.clients.lock().unwrap().get(&client) {
+            let _ = client.tx.send(to_message(message));
+        }
+    }
+
+    pub fn broadcast(&self, message: Message) {
+        for client in self.clients.lock().unwrap().values() {
+            let _ = client.tx.send(message.clone());
+        }
+    }
+}
+
+fn to_message(message: &impl Serialize) -> Message {
+    Message::Text(serde_json::to_string(message).unwrap_or_default())
+}
 
 pub async fn run_web_server(
-    port: u16,
+    addr: SocketAddr,
+    access: Access,
     svg_path: impl AsRef<Path>,
     html_path: impl AsRef<Path>,
-    json_path: impl AsRef<Path>,
-    mut reload_rx: UnboundedReceiver<()>,
+    session: Session,
+    reload_rx: UnboundedReceiver<Update>,
 ) -> Result<(), anyhow::Error> {
     let svg_path = svg_path.as_ref();
-    let json_path = json_path.as_ref();
     let html_path = html_path.as_ref();
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    eprintln!("Serving on http://localhost:{}", port);
+    eprintln!("Serving on http://{}", addr);
+    eprintln!("  http://{}/svg serves {}", addr, svg_path.display());
+    eprintln!("  http://{}/html serves {}", addr, html_path.display());
     eprintln!(
-        "  http://localhost:{}/svg serves {}",
-        port,
-        svg_path.display()
+        "  http://{}/json serves the graph as it was last saved",
+        addr
     );
     eprintln!(
-        "  http://localhost:{}/html serves {}",
-        port,
-        html_path.display()
+        "  http://{}/api/commands changes the graph; see the README for more",
+        addr
     );
+    describe_access(addr, &access);
+
+    let app = graph_routes("Microdot", svg_path, session, reload_rx)
+        .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") })
+        .layer(middleware::from_fn_with_state(access, require_token));
+
+    axum_server::bind(addr)
+        .serve(app.into_make_service())
+        .await
+        .map_err(|e| e.into())
+}
+
+/// Serves every graph in a directory, without a repl, each under `/graphs/<name>/` with the same
+/// routes `run_web_server` has for one graph.
+pub async fn run_dir_server(
+    addr: SocketAddr,
+    access: Access,
+    graphs: GraphDir,
+) -> Result<(), anyhow::Error> {
     eprintln!(
-        "  http://localhost:{}/json serves {}",
-        port,
-        json_path.display()
+        "Serving the graphs in {} on http://{}",
+        graphs.dir.display(),
+        addr
     );
+    for name in graphs.names()? {
+        eprintln!("  http://{}/graphs/{}/html", addr, name);
+    }
+    describe_access(addr, &access);
 
-    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+    // stopping with ctrl-c closes the graphs, so they're unlocked.
+    let handle = axum_server::Handle::new();
+    let shutdown = handle.clone();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        shutdown.graceful_shutdown(Some(Duration::from_secs(1)));
+    });
 
-    let html_handler = get(move || async {
-        let html = ImagePage::new("Microdot", "/svg");
-        html.render().map(|html_content| {
-            ([(axum::http::header::CONTENT_TYPE, "text/html")], html_content)
-        })
+    let graphs = Arc::new(graphs);
+    axum_server::bind(addr)
+        .handle(handle)
+        .serve(graphs.clone().routes(access).into_make_service())
+        .await?;
+    graphs.graphs.lock().unwrap().clear();
+    Ok(())
+}
+
+/// says who can use the server, and warns if it's open to the network.
+fn describe_access(addr: SocketAddr, access: &Access) {
+    if !access.is_open() {
+        eprintln!("  a token is needed, as ?token=<token> or an Authorization: Bearer header");
+    } else if !addr.ip().is_loopback() {
+        eprintln!(
+            "  anyone who can reach {} can change the graph; use --token to stop them",
+            addr
+        );
+    }
+}
+
+/// the diagram, graph file, editor, websocket and api of one graph; the editor finds the rest
+/// relative to its own address, so these can be served under any prefix.
+fn graph_routes(
+    title: &str,
+    svg_path: &Path,
+    session: Session,
+    reload_rx: UnboundedReceiver<Update>,
+) -> Router {
+    let clients: Clients = Arc::new(Hub::default());
+
+    let title = title.to_string();
+    let html_handler = get(move || async move {
+        let html = EditorPage { title };
+        html.render()
+            .map(|html_content| {
+                (
+                    [(axum::http::header::CONTENT_TYPE, "text/html")],
+                    html_content,
+                )
+            })
             .map(|html_content| (StatusCode::OK, html_content))
             .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
     });
 
+    let json_session = session.clone();
+    let json_handler = get(move || saved_json(json_session.clone()));
+
     let ws_clients = clients.clone();
-    let ws_handler = get(move |ws: WebSocketUpgrade| {
-        let ws_clients = ws_clients.clone();
-        async move {
-            ws.on_upgrade(move |socket| handle_socket(socket, ws_clients))
-        }
-    });
+    let ws_session = session.clone();
+    let ws_handler = get(
+        move |ws: WebSocketUpgrade,
+              Extension(permission): Extension<Permission>,
+              Query(query): Query<SocketQuery>| {
+            let ws_clients = ws_clients.clone();
+            let ws_session = ws_session.clone();
+            async move {
+                ws.on_upgrade(move |socket| {
+                    handle_socket(socket, ws_clients, ws_session, query.name, permission)
+                })
+            }
+        },
+    );
+
+    tokio::spawn(pass_on_updates(
+        clients.clone(),
+        svg_path.to_path_buf(),
+        reload_rx,
+    ));
 
-    let app = Router::new()
+    Router::new()
         .nest_service("/svg", ServeFile::new(svg_path))
-        .nest_service("/json", ServeFile::new(json_path))
+        .nest_service("/json", json_handler)
         .nest_service("/html", html_handler)
-        .route("/ws", ws_handler)
-        .with_state(clients.clone())
-        .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") });
+        // commands come over the socket, so other sites' pages mustn't open one.
+        .route(
+            "/ws",
+            ws_handler.layer(middleware::from_fn(require_same_origin)),
+        )
+        .with_state(clients)
+        .merge(api::routes(session))
+}
 
-    // Spawn a task to listen for reload events and broadcast to clients
-    let clients_for_reload = clients.clone();
-    tokio::spawn(async move {
-        while reload_rx.recv().await.is_some() {
-            broadcast_reload(&clients_for_reload);
+/// the graph as the session last saved it, wherever it's stored.
+async fn saved_json(session: Session) -> Result<impl IntoResponse, ApiError> {
+    let saved = tokio::task::spawn_blocking(move || session.saved_json())
+        .await
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    match saved {
+        Some(json) => Ok((
+            [(axum::http::header::CONTENT_TYPE, "application/json")],
+            json,
+        )),
+        None => Err(api_error(
+            StatusCode::NOT_FOUND,
+            "the graph hasn't been saved yet",
+        )),
+    }
+}
+
+/// passes news of the graph on to clients; the new diagram each time it's rendered, and
+/// everyone's changes.
+async fn pass_on_updates(
+    clients: Clients,
+    svg_path: PathBuf,
+    mut reload_rx: UnboundedReceiver<Update>,
+) {
+    while let Some(update) = reload_rx.recv().await {
+        let message = match update {
+            Update::Reload => match tokio::fs::read_to_string(&svg_path).await {
+                Ok(svg) => to_message(&ServerMessage::Svg { svg }),
+                Err(e) => {
+                    eprintln!("could not read {}: {}", svg_path.display(), e);
+                    continue;
+                }
+            },
+            update => to_message(&update),
+        };
+        clients.broadcast(message);
+    }
+}
+
+/// A graph opened to be served from a directory.
+pub struct ServedGraph {
+    pub session: Session,
+    pub reload_rx: UnboundedReceiver<Update>,
+    pub svg_path: PathBuf,
+    /// kept for as long as the graph is served.
+    pub lock: Option<GraphLock>,
+}
+
+type OpenGraph = dyn Fn(&Path) -> Result<ServedGraph, anyhow::Error> + Send + Sync;
+
+/// a served graph's routes, and its lock, once it's been opened.
+type Opened = Arc<OnceCell<(Router, Option<GraphLock>)>>;
+
+/// The graphs in a directory, each opened the first time someone asks for it.
+pub struct GraphDir {
+    dir: PathBuf,
+    open: Box<OpenGraph>,
+    /// each graph someone has asked for, opened once by whoever asked first; the map is only
+    /// locked to find a graph, so opening one doesn't hold up requests for the others.
+    graphs: Mutex<BTreeMap<String, Opened>>,
+}
+
+impl GraphDir {
+    /// `open` is given the graph's file, and sets up its session.
+    pub fn new(
+        dir: PathBuf,
+        open: impl Fn(&Path) -> Result<ServedGraph, anyhow::Error> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            dir,
+            open: Box::new(open),
+            graphs: Default::default(),
         }
-    });
+    }
 
-    axum_server::bind(addr)
-        .serve(app.into_make_service())
+    /// the graphs in the directory, named after their files, leaving out the diagrams of
+    /// read-only sessions.
+    pub fn names(&self) -> Result<Vec<String>, anyhow::Error> {
+        Ok(FileStore::new(self.dir.clone())
+            .names()?
+            .into_iter()
+            .filter(|name| !name.ends_with(".read-only.json"))
+            .filter_map(|name| name.strip_suffix(".json").map(str::to_string))
+            .collect())
+    }
+
+    fn routes(self: Arc<Self>, access: Access) -> Router {
+        Router::new()
+            .route("/", get(graph_index))
+            .route("/graphs/:name", get(serve_graph))
+            .route("/graphs/:name/*rest", get(serve_graph).post(serve_graph))
+            .with_state(self)
+            .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") })
+            .layer(middleware::from_fn_with_state(access, require_token))
+    }
+
+    /// the routes for a graph, opening it if no one has asked for it yet; `None` if there's no
+    /// such graph.
+    async fn graph(self: &Arc<Self>, name: &str) -> Result<Option<Router>, anyhow::Error> {
+        let asked_for = self.graphs.lock().unwrap().get(name).cloned();
+        let graph = match asked_for {
+            Some(graph) => graph,
+            None => {
+                if !self.names()?.iter().any(|known| known == name) {
+                    return Ok(None);
+                }
+                // someone else may have asked for it meanwhile, in which case it's theirs to open.
+                let mut graphs = self.graphs.lock().unwrap();
+                graphs.entry(name.to_string()).or_default().clone()
+            }
+        };
+
+        let (router, _lock) = graph
+            .get_or_try_init(|| async {
+                let json_file = self.dir.join(format!("{}.json", name));
+                let graph_dir = self.clone();
+                let served =
+                    tokio::task::spawn_blocking(move || (graph_dir.open)(&json_file)).await??;
+                let router = graph_routes(name, &served.svg_path, served.session, served.reload_rx);
+                Ok::<_, anyhow::Error>((router, served.lock))
+            })
+            .await?;
+        Ok(Some(router.clone()))
+    }
+}
+
+async fn graph_index(
+    State(graphs): State<Arc<GraphDir>>,
+    Query(query): Query<TokenQuery>,
+) -> Result<Html<String>, ApiError> {
+    let names = graphs
+        .names()
+        .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))?;
+    IndexPage {
+        dir: graphs.dir.display().to_string(),
+        names,
+        token_query: query
+            .token
+            .and_then(|token| askama::filters::urlencode_strict(token).ok())
+            .map(|token| format!("?token={}", token))
+            .unwrap_or_default(),
+    }
+    .render()
+    .map(Html)
+    .map_err(|e| api_error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// passes a request under `/graphs/<name>` on to that graph's routes.
+async fn serve_graph(
+    State(graphs): State<Arc<GraphDir>>,
+    extract::Path(params): extract::Path<BTreeMap<String, String>>,
+    mut request: Request,
+) -> Response {
+    let name = params.get("name").cloned().unwrap_or_default();
+    let router = match graphs.graph(&name).await {
+        Ok(Some(router)) => router,
+        Ok(None) => {
+            return api_error(StatusCode::NOT_FOUND, format!("no graph {}", name)).into_response()
+        }
+        Err(e) => {
+            return api_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("could not open {}: {}", name, e),
+            )
+            .into_response()
+        }
+    };
+
+    let rest = params
+        .get("rest")
+        .cloned()
+        .unwrap_or_else(|| "html".to_string());
+    let query = request
+        .uri()
+        .query()
+        .map(|query| format!("?{}", query))
+        .unwrap_or_default();
+    match format!("/{}{}", rest, query).parse() {
+        Ok(uri) => *request.uri_mut() = uri,
+        Err(e) => return api_error(StatusCode::BAD_REQUEST, e).into_response(),
+    }
+    router
+        .fallback(|| async { (StatusCode::NOT_FOUND, "Not Found") })
+        .oneshot(request)
         .await
-        .map_err(|e| e.into())
+        .into_response()
+}
+
+/// The list of graphs `run_dir_server` serves, at `/`.
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexPage {
+    dir: String,
+    names: Vec<String>,
+    /// `?token=...` to pass on in the links, so the graphs open with the token the index was
+    /// opened with.
+    token_query: String,
+}
+
+/// The interactive page served at `/html`; it edits the graph over `/ws`.
+#[derive(Template)]
+#[template(path = "editor.html")]
+struct EditorPage {
+    title: String,
 }
 
-async fn handle_socket(mut socket: WebSocket, clients: Clients) {
+async fn handle_socket(
+    socket: WebSocket,
+    clients: Clients,
+    session: Session,
+    name: Option<String>,
+    permission: Permission,
+) {
     let (mut sender, mut receiver) = socket.split();
     let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
-    // Add this sender to the list
-    {
-        let mut clients = clients.lock().unwrap();
-        clients.push(tx);
-    }
+    let (client, name) = clients.join(name, tx);
+    clients.send(client, &ServerMessage::Welcome { you: name.clone() });
+    clients.send(
+        client,
+        &ServerMessage::Selections {
+            selections: clients.selections(),
+        },
+    );
 
     // Forward messages from server to client
     let send_task = tokio::spawn(async move {
@@ -99,20 +528,348 @@ async fn handle_socket(mut socket: WebSocket, clients: Clients) {
         }
     });
 
-    // Optionally, handle incoming messages from client (not needed for reload)
+    // Clients send their selection, or commands as JSON, the same as the api takes; the reply to
+    // a command goes to the sender, and everyone hears about the change.
+    let recv_clients = clients.clone();
     let recv_task = tokio::spawn(async move {
-        while let Some(Ok(_msg)) = receiver.next().await {
-            // Ignore client messages
+        while let Some(Ok(msg)) = receiver.next().await {
+            if let Message::Text(text) = msg {
+                handle_message(&recv_clients, &session, client, &name, permission, &text).await;
+            }
         }
     });
 
-    let _ = tokio::join!(send_task, recv_task);
+    let _ = recv_task.await;
+    clients.leave(client);
+    send_task.abort();
 }
 
-/// Call this to broadcast a reload message to all websocket clients
-pub fn broadcast_reload(clients: &Clients) {
-    let clients = clients.lock().unwrap();
-    for tx in clients.iter() {
-        let _ = tx.send(Message::Text("reload".to_string()));
+async fn handle_message(
+    hub: &Hub,
+    session: &Session,
+    client: usize,
+    name: &str,
+    permission: Permission,
+    text: &str,
+) {
+    match serde_json::from_str::<ClientMessage>(text) {
+        Ok(ClientMessage::Select(SelectRequest { select })) => hub.select(client, select),
+        Ok(ClientMessage::Command(_)) if permission < Permission::Write => hub.send(
+            client,
+            &ServerMessage::Error(ErrorResponse {
+                error: READ_ONLY.to_string(),
+            }),
+        ),
+        Ok(ClientMessage::Command(request)) => {
+            let reply = match run_command(session.clone(), name, request).await {
+                Ok(response) => ServerMessage::Result(response),
+                Err((_, error)) => ServerMessage::Error(error.0),
+            };
+            hub.send(client, &reply);
+        }
+        Err(e) => hub.send(
+            client,
+            &ServerMessage::Error(ErrorResponse {
+                error: format!("could not understand '{}': {}", text, e),
+            }),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::{JsonExporter, JsonImporter};
+    use crate::session::test_session;
+    use crate::storage::SqliteStore;
+    use axum::body::Body;
+    use microdot_core::command::GraphCommand;
+    use microdot_core::graph::Graph;
+    use microdot_core::Label;
+    use std::sync::RwLock;
+    use tokio::sync::mpsc;
+
+    fn received(rx: &mut UnboundedReceiver<Message>) -> Vec<String> {
+        let mut messages = vec![];
+        while let Ok(Message::Text(text)) = rx.try_recv() {
+            messages.push(text);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn clients_share_changes_and_selections() {
+        let dir = tempfile::tempdir().unwrap();
+        let (session, mut reload_rx) = test_session(&dir.path().join("web.json"), None);
+
+        let hub = Hub::default();
+        let (alice_tx, mut alice_rx) = mpsc::unbounded_channel();
+        let (bob_tx, mut bob_rx) = mpsc::unbounded_channel();
+        let (alice, alice_name) = hub.join(Some("alice".to_string()), alice_tx);
+        let (_, bob_name) = hub.join(None, bob_tx);
+        assert_eq!(bob_name, "visitor 2");
+
+        handle_message(
+            &hub,
+            &session,
+            alice,
+            &alice_name,
+            Permission::Write,
+            r#"{"cmd": "i from alice"}"#,
+        )
+        .await;
+        assert_eq!(
+            received(&mut alice_rx),
+            vec![
+                r#"{"type":"result","result":"inserted node n0: 'from alice'","graph":"Graph: 1 nodes, 0 edges"}"#
+            ]
+        );
+        assert!(received(&mut bob_rx).is_empty());
+        assert_eq!(
+            reload_rx.try_recv().unwrap(),
+            Update::Applied {
+                by: "alice".to_string(),
+                command: GraphCommand::InsertNode {
+                    label: Label::new("from alice")
+                },
+                result: "inserted node n0: 'from alice'".to_string(),
+            }
+        );
+
+        handle_message(
+            &hub,
+            &session,
+            alice,
+            &alice_name,
+            Permission::Read,
+            r#"{"select": "n0"}"#,
+        )
+        .await;
+        let selections = r#"{"type":"selections","selections":[{"by":"alice","id":"n0"}]}"#;
+        assert_eq!(received(&mut bob_rx), vec![selections]);
+        assert_eq!(received(&mut alice_rx), vec![selections]);
+
+        handle_message(
+            &hub,
+            &session,
+            alice,
+            &alice_name,
+            Permission::Write,
+            "reload",
+        )
+        .await;
+        assert!(received(&mut alice_rx)[0]
+            .starts_with(r#"{"type":"error","error":"could not understand 'reload'"#));
+
+        hub.leave(alice);
+        assert_eq!(
+            received(&mut bob_rx),
+            vec![r#"{"type":"selections","selections":[]}"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn sends_each_new_diagram_to_clients() {
+        let dir = tempfile::tempdir().unwrap();
+        let svg_path = dir.path().join("web.svg");
+        std::fs::write(&svg_path, "<svg>n0</svg>").unwrap();
+
+        let hub = Arc::new(Hub::default());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        hub.join(None, tx);
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        reload_tx.send(Update::Reload).unwrap();
+        drop(reload_tx);
+        pass_on_updates(hub, svg_path, reload_rx).await;
+
+        assert_eq!(
+            received(&mut rx),
+            vec![r#"{"type":"svg","svg":"<svg>n0</svg>"}"#]
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_the_json_from_wherever_the_graph_is_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("stored.json");
+        let store = SqliteStore::in_memory().unwrap();
+        let (session, reload_rx) = test_session(&json_file, Some(Box::new(store)));
+        let routes = graph_routes(
+            "stored",
+            &json_file.with_extension("svg"),
+            session.clone(),
+            reload_rx,
+        );
+        let json = |routes: Router| async move {
+            let response = routes.oneshot(get("/json")).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            (status, String::from_utf8_lossy(&body).to_string())
+        };
+
+        assert_eq!(json(routes.clone()).await.0, StatusCode::NOT_FOUND);
+
+        session
+            .apply(
+                "api",
+                GraphCommand::InsertNode {
+                    label: Label::new("only in the database"),
+                },
+            )
+            .unwrap();
+        let (status, body) = json(routes).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("only in the database"), "{}", body);
+        assert!(!json_file.exists());
+    }
+
+    #[tokio::test]
+    async fn other_sites_cannot_open_the_websocket() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_file = dir.path().join("ws.json");
+        let (session, reload_rx) = test_session(&json_file, None);
+        let routes =
+            graph_routes("ws", &json_file.with_extension("svg"), session, reload_rx).layer(
+                middleware::from_fn_with_state(Access::default(), require_token),
+            );
+        let status = |origin: &str| {
+            let request = Request::get("/ws")
+                .header("host", "localhost:7777")
+                .header("origin", origin)
+                .header("connection", "upgrade")
+                .header("upgrade", "websocket")
+                .header("sec-websocket-version", "13")
+                .header("sec-websocket-key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .body(Body::empty())
+                .unwrap();
+            let routes = routes.clone();
+            async move { routes.oneshot(request).await.unwrap().status() }
+        };
+
+        assert_eq!(status("https://example.com").await, StatusCode::FORBIDDEN);
+        // a real connection would be upgraded; this one has nothing to upgrade.
+        assert_ne!(status("http://localhost:7777").await, StatusCode::FORBIDDEN);
+    }
+
+    async fn body(graphs: &Arc<GraphDir>, request: Request) -> (StatusCode, String) {
+        let response = graphs
+            .clone()
+            .routes(Access::default())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, String::from_utf8_lossy(&body).to_string())
+    }
+
+    fn get(uri: &str) -> Request {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    /// opens a graph in a directory the way `serve --dir` does, without locking it.
+    fn open(json_file: &std::path::Path) -> Result<ServedGraph, anyhow::Error> {
+        let (store, name) = FileStore::for_file(json_file);
+        let (reload_tx, reload_rx) = mpsc::unbounded_channel();
+        Ok(ServedGraph {
+            session: Session::new(
+                Arc::new(RwLock::new(JsonImporter::load_from_store(
+                    &store, &name, false,
+                )?)),
+                json_file.to_path_buf(),
+                Arc::new(store),
+                name,
+                reload_tx,
+            ),
+            reload_rx,
+            svg_path: json_file.with_extension("svg"),
+            lock: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn serves_each_graph_in_a_directory_once_asked_for() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["first", "second"] {
+            let json = JsonExporter::new().export_json(&Graph::new());
+            std::fs::write(dir.path().join(format!("{}.json", name)), json).unwrap();
+        }
+        let opened = Arc::new(Mutex::new(vec![]));
+        let opened_by_dir = opened.clone();
+        let graphs = Arc::new(GraphDir::new(dir.path().to_path_buf(), move |json_file| {
+            opened_by_dir.lock().unwrap().push(json_file.to_path_buf());
+            open(json_file)
+        }));
+
+        let (status, index) = body(&graphs, get("/")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(index.contains(r#"href="graphs/first/html""#));
+        assert!(index.contains(r#"href="graphs/second/html""#));
+        assert!(opened.lock().unwrap().is_empty());
+
+        let command = Request::post("/graphs/second/api/commands")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"cmd": "i only in the second"}"#))
+            .unwrap();
+        let (status, _) = body(&graphs, command).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, nodes) = body(&graphs, get("/graphs/second/api/nodes")).await;
+        assert!(nodes.contains("only in the second"));
+        let (_, nodes) = body(&graphs, get("/graphs/first/api/nodes")).await;
+        assert_eq!(nodes, "[]");
+        assert_eq!(
+            *opened.lock().unwrap(),
+            vec![
+                dir.path().join("second.json"),
+                dir.path().join("first.json")
+            ]
+        );
+
+        let (status, _) = body(&graphs, get("/graphs/third/html")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = body(&graphs, get("/graphs/..%2Fsecret/json")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn opening_one_graph_does_not_hold_up_the_others() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["slow", "fast"] {
+            let json = JsonExporter::new().export_json(&Graph::new());
+            std::fs::write(dir.path().join(format!("{}.json", name)), json).unwrap();
+        }
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (finish_tx, finish_rx) = std::sync::mpsc::channel::<()>();
+        let slow_open = Mutex::new((started_tx, finish_rx));
+        let graphs = Arc::new(GraphDir::new(dir.path().to_path_buf(), move |json_file| {
+            if json_file.ends_with("slow.json") {
+                let (started, finish) = &*slow_open.lock().unwrap();
+                started.send(()).unwrap();
+                finish.recv().unwrap();
+            }
+            open(json_file)
+        }));
+
+        let slow_graphs = graphs.clone();
+        let slow =
+            tokio::spawn(async move { body(&slow_graphs, get("/graphs/slow/api/nodes")).await });
+        tokio::task::spawn_blocking(move || started_rx.recv().unwrap())
+            .await
+            .unwrap();
+
+        let fast = tokio::time::timeout(
+            Duration::from_secs(5),
+            body(&graphs, get("/graphs/fast/api/nodes")),
+        )
+        .await
+        .expect("the fast graph waited for the slow one to open");
+        assert_eq!(fast, (StatusCode::OK, "[]".to_string()));
+
+        finish_tx.send(()).unwrap();
+        assert_eq!(slow.await.unwrap(), (StatusCode::OK, "[]".to_string()));
     }
 }